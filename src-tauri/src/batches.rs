@@ -0,0 +1,54 @@
+// Copyright (C) 2025  Athan Clark
+use crate::types::Document;
+
+// NOTE: Rows are streamed while syncing, and written in batches of whichever limit is reached first,
+// so memory stays flat no matter how large the library is
+const BATCH_ROWS: usize = 100;
+const BATCH_BYTES: usize = 8 * 1024 * 1024;
+/// How many ids are looked up per query, since databases limit how many parameters can be bound.
+pub const ID_CHUNK_SIZE: usize = 1000;
+
+/// Rows waiting to be written.
+pub struct Batch<T> {
+    rows: Vec<T>,
+    bytes: usize,
+}
+
+impl<T> Default for Batch<T> {
+    fn default() -> Self {
+        Batch {
+            rows: vec![],
+            bytes: 0,
+        }
+    }
+}
+
+impl<T> Batch<T> {
+    /// Adds a row of about `bytes` in size, returning the rows to write once the batch is full.
+    pub fn push(&mut self, row: T, bytes: usize) -> Option<Vec<T>> {
+        self.rows.push(row);
+        self.bytes += bytes;
+        if self.rows.len() >= BATCH_ROWS || self.bytes >= BATCH_BYTES {
+            self.bytes = 0;
+            Some(std::mem::take(&mut self.rows))
+        } else {
+            None
+        }
+    }
+
+    /// The rows left over once everything was pushed.
+    pub fn finish(self) -> Option<Vec<T>> {
+        if self.rows.is_empty() {
+            None
+        } else {
+            Some(self.rows)
+        }
+    }
+}
+
+/// Roughly how much memory a document takes -- its content, for all but the smallest documents.
+pub fn document_size(document: &Document) -> usize {
+    document.content.as_ref().map_or(0, |c| c.len())
+        + document.name.as_ref().map_or(0, |n| n.len())
+        + document.rendered.as_ref().map_or(0, |r| r.len())
+}
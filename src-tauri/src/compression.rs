@@ -0,0 +1,109 @@
+// Copyright (C) 2025  Athan Clark
+use crate::types::Document;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+// NOTE: Compressed values are stored as `zstd:<base64 of the compressed content>`. Only the content
+// of documents is compressed -- it's the only field that gets large.
+const COMPRESSED_PREFIX: &str = "zstd:";
+const COMPRESSION_LEVEL: i32 = 3;
+// NOTE: Below this, base64 eats whatever compression saves
+const MIN_COMPRESSED_LENGTH: usize = 1024;
+/// Where a remote's `metadata` records the codec new content is pushed with.
+pub const COMPRESSION_METADATA: &str = "compression";
+pub const ZSTD: &str = "zstd";
+
+fn compress_content(compress: bool, value: Option<String>) -> Result<Option<String>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    // NOTE: Content that happens to start with the prefix is always compressed, so it can't be
+    // mistaken for compressed content when it's pulled
+    let forced = value.starts_with(COMPRESSED_PREFIX);
+    let worthwhile = compress && value.len() >= MIN_COMPRESSED_LENGTH;
+    if !forced && !worthwhile {
+        return Ok(Some(value));
+    }
+    let compressed =
+        zstd::encode_all(value.as_bytes(), COMPRESSION_LEVEL).map_err(|e| e.to_string())?;
+    let encoded = format!("{COMPRESSED_PREFIX}{}", STANDARD.encode(compressed));
+    if forced || encoded.len() < value.len() {
+        Ok(Some(encoded))
+    } else {
+        Ok(Some(value))
+    }
+}
+
+fn decompress_content(id: &str, value: Option<String>) -> Result<Option<String>, String> {
+    let Some(encoded) = value
+        .as_deref()
+        .and_then(|v| v.strip_prefix(COMPRESSED_PREFIX))
+    else {
+        return Ok(value);
+    };
+    let compressed = STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("couldn't decompress documents.content of {id}: {e}"))?;
+    let decompressed = zstd::decode_all(compressed.as_slice())
+        .map_err(|e| format!("couldn't decompress documents.content of {id}: {e}"))?;
+    String::from_utf8(decompressed)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Compresses a document's content before it's pushed, when the remote compresses content and it's
+/// large enough to be worth it. Done before encrypting, since ciphertext doesn't compress.
+pub fn compress_document(compress: bool, document: Document) -> Result<Document, String> {
+    Ok(Document {
+        content: compress_content(compress, document.content)?,
+        ..document
+    })
+}
+
+/// Decompresses a pulled document's content, if it was compressed -- whether or not the remote
+/// compresses content now.
+pub fn decompress_document(document: Document) -> Result<Document, String> {
+    Ok(Document {
+        content: decompress_content(&document.id, document.content)?,
+        ..document
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(compress: bool, value: &str) -> String {
+        let compressed = compress_content(compress, Some(value.to_string())).unwrap();
+        let decompressed = decompress_content("d1", compressed.clone()).unwrap();
+        assert_eq!(decompressed.as_deref(), Some(value));
+        compressed.unwrap()
+    }
+
+    #[test]
+    fn large_content() {
+        let value = "All work and no play makes Jack a dull boy.\n".repeat(100);
+        let compressed = round_trip(true, &value);
+        assert!(compressed.starts_with(COMPRESSED_PREFIX));
+        assert!(compressed.len() < value.len());
+        assert_eq!(round_trip(false, &value), value);
+    }
+
+    #[test]
+    fn small_content() {
+        assert_eq!(round_trip(true, "hello"), "hello");
+    }
+
+    #[test]
+    fn content_with_the_prefix() {
+        for compress in [true, false] {
+            let compressed = round_trip(compress, "zstd:not actually compressed");
+            assert_ne!(compressed, "zstd:not actually compressed");
+        }
+    }
+
+    #[test]
+    fn no_content() {
+        assert_eq!(compress_content(true, None).unwrap(), None);
+        assert_eq!(decompress_content("d1", None).unwrap(), None);
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::ScriptoriumError;
+use crate::types::{Book, Document};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use std::sync::Mutex;
+
+// NOTE: Encrypted values are stored as `enc:v1:<base64 of nonce and ciphertext>`. Anything
+// without the prefix is plaintext, so rows pushed before encryption was enabled still read fine.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LENGTH: usize = 24;
+const SALT_LENGTH: usize = 16;
+// NOTE: Encrypted into `key_check` on each remote, so a wrong passphrase is caught before any
+// rows are written with it
+const KEY_CHECK_PLAINTEXT: &str = "scriptorium";
+const KEY_CHECK_FIELD: &str = "key_check";
+
+struct Unlocked {
+    passphrase: String,
+    credentials: LibraryKey,
+}
+
+/// The master passphrase entered in the app, kept in memory only. Keys for remotes are derived
+/// from it per remote, using the salt stored in that remote's `key_check` table, and the key for
+/// saved passwords is derived with the salt stored in the local settings.
+#[derive(Default)]
+pub struct EncryptionState(Mutex<Option<Unlocked>>);
+
+impl EncryptionState {
+    pub fn unlock(&self, passphrase: String, credentials: LibraryKey) {
+        *self.0.lock().unwrap() = Some(Unlocked {
+            passphrase,
+            credentials,
+        });
+    }
+
+    pub fn lock(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    pub fn passphrase(&self) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|u| u.passphrase.clone())
+    }
+
+    pub fn credentials_key(&self) -> Option<LibraryKey> {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|u| u.credentials.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct LibraryKey(XChaCha20Poly1305);
+
+impl LibraryKey {
+    pub fn derive(passphrase: &str, salt: &str) -> Result<LibraryKey, String> {
+        let salt = STANDARD.decode(salt).map_err(|e| e.to_string())?;
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| e.to_string())?;
+        Ok(LibraryKey(XChaCha20Poly1305::new(&key.into())))
+    }
+
+    pub fn new_salt() -> String {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        STANDARD.encode(salt)
+    }
+
+    pub fn check_value(&self) -> Result<String, String> {
+        self.encrypt(KEY_CHECK_FIELD, "", KEY_CHECK_PLAINTEXT)
+    }
+
+    pub fn verify(&self, check_value: &str) -> Result<(), ScriptoriumError> {
+        match self.decrypt(KEY_CHECK_FIELD, "", check_value) {
+            Ok(value) if value == KEY_CHECK_PLAINTEXT => Ok(()),
+            _ => Err(ScriptoriumError::Encryption {
+                message: "the encryption passphrase doesn't match this remote's".to_string(),
+            }),
+        }
+    }
+
+    // NOTE: The field and row id are authenticated along with the value, so ciphertext can't be
+    // swapped between rows or columns on the server without failing to decrypt
+    fn encrypt(&self, field: &str, id: &str, value: &str) -> Result<String, String> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+        let aad = format!("{field}:{id}");
+        let ciphertext = self
+            .0
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        let mut bytes = nonce.to_vec();
+        bytes.extend(ciphertext);
+        Ok(format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(bytes)))
+    }
+
+    fn decrypt(&self, field: &str, id: &str, value: &str) -> Result<String, String> {
+        // NOTE: A plaintext value where ciphertext is expected could have been put there by the
+        // server, so it isn't trusted
+        let Some(encoded) = value.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Err(format!("{field} of {id} isn't encrypted"));
+        };
+        let bytes = STANDARD.decode(encoded).map_err(|e| e.to_string())?;
+        if bytes.len() < NONCE_LENGTH {
+            return Err(format!(
+                "couldn't decrypt {field} of {id}: value is too short"
+            ));
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let aad = format!("{field}:{id}");
+        let plaintext = self
+            .0
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("couldn't decrypt {field} of {id}"))?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+}
+
+fn encrypt_field(
+    key: Option<&LibraryKey>,
+    field: &str,
+    id: &str,
+    value: Option<String>,
+) -> Result<Option<String>, String> {
+    match (key, value) {
+        (Some(key), Some(value)) => key.encrypt(field, id, &value).map(Some),
+        (_, value) => Ok(value),
+    }
+}
+
+fn decrypt_field(
+    key: Option<&LibraryKey>,
+    field: &str,
+    id: &str,
+    value: Option<String>,
+) -> Result<Option<String>, String> {
+    match (key, value) {
+        (Some(key), Some(value)) => key.decrypt(field, id, &value).map(Some),
+        (None, Some(value)) if is_encrypted(&value) => Err(format!(
+            "{field} of {id} is encrypted, but the library is locked"
+        )),
+        (_, value) => Ok(value),
+    }
+}
+
+pub fn encrypt_book(key: Option<&LibraryKey>, book: Book) -> Result<Book, String> {
+    Ok(Book {
+        name: encrypt_field(key, "books.name", &book.id, book.name)?,
+        ..book
+    })
+}
+
+pub fn decrypt_book(key: Option<&LibraryKey>, book: Book) -> Result<Book, String> {
+    Ok(Book {
+        name: decrypt_field(key, "books.name", &book.id, book.name)?,
+        ..book
+    })
+}
+
+pub fn encrypt_document(key: Option<&LibraryKey>, document: Document) -> Result<Document, String> {
+    Ok(Document {
+        name: encrypt_field(key, "documents.name", &document.id, document.name)?,
+        content: encrypt_field(key, "documents.content", &document.id, document.content)?,
+        // NOTE: A hash of the plaintext would let the server confirm guesses at it
+        content_hash: document.content_hash.filter(|_| key.is_none()),
+        ..document
+    })
+}
+
+pub fn decrypt_document(key: Option<&LibraryKey>, document: Document) -> Result<Document, String> {
+    Ok(Document {
+        name: decrypt_field(key, "documents.name", &document.id, document.name)?,
+        content: decrypt_field(key, "documents.content", &document.id, document.content)?,
+        ..document
+    })
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+pub fn encrypt_password(
+    key: &LibraryKey,
+    remote_id: &str,
+    password: &str,
+) -> Result<String, String> {
+    key.encrypt("remote_servers.password", remote_id, password)
+}
+
+pub fn decrypt_password(
+    key: &LibraryKey,
+    remote_id: &str,
+    password: &str,
+) -> Result<String, String> {
+    key.decrypt("remote_servers.password", remote_id, password)
+}
+
+/// Encrypts a proxy's password, for the remote with `owner` as its id, or for the global proxy
+/// setting.
+pub fn encrypt_proxy_password(
+    key: &LibraryKey,
+    owner: &str,
+    password: &str,
+) -> Result<String, String> {
+    key.encrypt("proxy_password", owner, password)
+}
+
+pub fn decrypt_proxy_password(
+    key: &LibraryKey,
+    owner: &str,
+    password: &str,
+) -> Result<String, String> {
+    key.decrypt("proxy_password", owner, password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(passphrase: &str, salt: &str) -> LibraryKey {
+        LibraryKey::derive(passphrase, salt).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let salt = LibraryKey::new_salt();
+        let encrypted = key("hunter2", &salt)
+            .encrypt("documents.content", "d1", "# Secret")
+            .unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("Secret"));
+        // NOTE: The same passphrase and salt derive the same key on every device
+        let decrypted = key("hunter2", &salt).decrypt("documents.content", "d1", &encrypted);
+        assert_eq!(decrypted.unwrap(), "# Secret");
+    }
+
+    #[test]
+    fn fresh_nonces() {
+        let key = key("hunter2", &LibraryKey::new_salt());
+        let first = key.encrypt("books.name", "b1", "Runbooks").unwrap();
+        let second = key.encrypt("books.name", "b1", "Runbooks").unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn swapped_ciphertext() {
+        let key = key("hunter2", &LibraryKey::new_salt());
+        let encrypted = key.encrypt("documents.name", "d1", "Notes").unwrap();
+        assert!(key.decrypt("documents.name", "d2", &encrypted).is_err());
+        assert!(key.decrypt("documents.content", "d1", &encrypted).is_err());
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let salt = LibraryKey::new_salt();
+        let right = key("hunter2", &salt);
+        let wrong = key("hunter3", &salt);
+        let check_value = right.check_value().unwrap();
+        assert!(right.verify(&check_value).is_ok());
+        assert!(matches!(
+            wrong.verify(&check_value),
+            Err(ScriptoriumError::Encryption { .. })
+        ));
+        let encrypted = right.encrypt("books.name", "b1", "Runbooks").unwrap();
+        assert!(wrong.decrypt("books.name", "b1", &encrypted).is_err());
+        // NOTE: Nor with the same passphrase under another salt
+        let other = key("hunter2", &LibraryKey::new_salt());
+        assert!(other.decrypt("books.name", "b1", &encrypted).is_err());
+    }
+
+    #[test]
+    fn plaintext_is_rejected() {
+        let key = key("hunter2", &LibraryKey::new_salt());
+        assert!(key.decrypt("books.name", "b1", "Runbooks").is_err());
+        assert!(key.decrypt("books.name", "b1", "enc:v1:AAAA").is_err());
+        assert!(decrypt_field(None, "books.name", "b1", Some("enc:v1:AAAA".into())).is_err());
+        let plaintext = decrypt_field(None, "books.name", "b1", Some("Runbooks".into()));
+        assert_eq!(plaintext.unwrap().as_deref(), Some("Runbooks"));
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright (C) 2025  Athan Clark
+use log::warn;
+use sqlx::{
+    error::DatabaseError, migrate::MigrateError, mysql::MySqlDatabaseError, sqlite::SqliteError,
+};
+use std::{fmt, future::Future, io::ErrorKind, time::Duration};
+
+const RETRY_ATTEMPTS: u32 = 4;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// What went wrong, as returned from every command. Each kind serializes with a stable `code` the
+/// frontend can act on, next to a message to show.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum ScriptoriumError {
+    /// The remote rejected the user or password.
+    AuthFailed {
+        message: String,
+    },
+    /// The TLS handshake failed, or a certificate couldn't be used.
+    TlsFailed {
+        message: String,
+    },
+    /// The host, SSH tunnel or proxy couldn't be reached, or stopped responding.
+    Unreachable {
+        message: String,
+    },
+    /// The remote's tables aren't what this version of Scriptorium expects.
+    SchemaMismatch {
+        message: String,
+    },
+    /// The library is locked, or its passphrase doesn't match the remote's.
+    Encryption {
+        message: String,
+    },
+    /// A document couldn't be parsed, at `line` when the parser says where.
+    Parse {
+        message: String,
+        line: Option<usize>,
+    },
+    /// Any other error from a database.
+    Database {
+        message: String,
+    },
+    Other {
+        message: String,
+    },
+}
+
+impl ScriptoriumError {
+    /// A parse error, at `line` when the parser reports one.
+    pub fn parse(message: String, line: Option<usize>) -> Self {
+        ScriptoriumError::Parse { message, line }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ScriptoriumError::AuthFailed { message }
+            | ScriptoriumError::TlsFailed { message }
+            | ScriptoriumError::Unreachable { message }
+            | ScriptoriumError::SchemaMismatch { message }
+            | ScriptoriumError::Encryption { message }
+            | ScriptoriumError::Parse { message, .. }
+            | ScriptoriumError::Database { message }
+            | ScriptoriumError::Other { message } => message,
+        }
+    }
+}
+
+impl fmt::Display for ScriptoriumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl From<String> for ScriptoriumError {
+    fn from(message: String) -> Self {
+        ScriptoriumError::Other { message }
+    }
+}
+
+impl From<sqlx::Error> for ScriptoriumError {
+    fn from(e: sqlx::Error) -> Self {
+        SyncError::from(e).into()
+    }
+}
+
+impl From<SyncError> for ScriptoriumError {
+    fn from(e: SyncError) -> Self {
+        match e {
+            SyncError::Transient(e) | SyncError::Permanent(e) => e,
+        }
+    }
+}
+
+/// Why syncing with a remote failed. Transient failures (e.g. a dropped connection or a deadlock)
+/// are likely to go away when tried again; permanent ones (e.g. a wrong password or a drifted
+/// schema) need someone to fix them.
+#[derive(Debug, Clone)]
+pub enum SyncError {
+    Transient(ScriptoriumError),
+    Permanent(ScriptoriumError),
+}
+
+impl SyncError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SyncError::Transient(_))
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Transient(e) | SyncError::Permanent(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<String> for SyncError {
+    fn from(e: String) -> Self {
+        SyncError::Permanent(e.into())
+    }
+}
+
+impl From<ScriptoriumError> for SyncError {
+    fn from(e: ScriptoriumError) -> Self {
+        SyncError::Permanent(e)
+    }
+}
+
+impl From<sqlx::Error> for SyncError {
+    fn from(e: sqlx::Error) -> Self {
+        let message = e.to_string();
+        let kind = match &e {
+            sqlx::Error::Tls(_) => ScriptoriumError::TlsFailed { message },
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => {
+                ScriptoriumError::Unreachable { message }
+            }
+            sqlx::Error::Database(e) if is_auth_failure(&**e) => {
+                ScriptoriumError::AuthFailed { message }
+            }
+            _ => ScriptoriumError::Database { message },
+        };
+        if is_transient(&e) {
+            SyncError::Transient(kind)
+        } else {
+            SyncError::Permanent(kind)
+        }
+    }
+}
+
+impl From<MigrateError> for SyncError {
+    fn from(e: MigrateError) -> Self {
+        match e {
+            MigrateError::Execute(e) | MigrateError::ExecuteMigration(e, _) => SyncError::from(e),
+            e => SyncError::Permanent(ScriptoriumError::SchemaMismatch {
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+// NOTE: PostgreSQL's SQLSTATE class for rejected authorization, and MySQL's error numbers for a
+// denied user, database or password
+const AUTH_SQLSTATE_CLASS: &str = "28";
+const AUTH_MYSQL_ERRORS: [u16; 3] = [1044, 1045, 1698];
+
+fn is_auth_failure(e: &dyn DatabaseError) -> bool {
+    if let Some(e) = e.try_downcast_ref::<MySqlDatabaseError>() {
+        AUTH_MYSQL_ERRORS.contains(&e.number())
+    } else if e.try_downcast_ref::<SqliteError>().is_some() {
+        false
+    } else {
+        e.code().is_some_and(|c| c.starts_with(AUTH_SQLSTATE_CLASS))
+    }
+}
+
+// NOTE: PostgreSQL's SQLSTATEs for serialization failures, deadlocks, lock timeouts, cancelled
+// statements, servers shutting down or out of connections, and lost connections
+const TRANSIENT_SQLSTATES: [&str; 8] = [
+    "40001", "40P01", "55P03", "57014", "57P01", "57P02", "57P03", "53300",
+];
+// NOTE: MySQL's error numbers for too many connections, network timeouts, lock wait timeouts and
+// deadlocks
+const TRANSIENT_MYSQL_ERRORS: [u16; 7] = [1040, 1158, 1159, 1160, 1161, 1205, 1213];
+// NOTE: SQLite's primary result codes for a busy or locked database
+const TRANSIENT_SQLITE_CODES: [i32; 2] = [5, 6];
+
+fn is_transient(e: &sqlx::Error) -> bool {
+    match e {
+        // NOTE: A refused connection isn't here -- it's more likely a wrong host or port, or a server
+        // that's down, than anything a retry fixes
+        sqlx::Error::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::TimedOut
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::Interrupted
+                | ErrorKind::NotConnected
+        ),
+        sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(e) => {
+            if let Some(e) = e.try_downcast_ref::<MySqlDatabaseError>() {
+                TRANSIENT_MYSQL_ERRORS.contains(&e.number())
+            } else if let Some(e) = e.try_downcast_ref::<SqliteError>() {
+                e.code()
+                    .and_then(|c| c.parse::<i32>().ok())
+                    .is_some_and(|c| TRANSIENT_SQLITE_CODES.contains(&(c & 0xff)))
+            } else {
+                e.code()
+                    .is_some_and(|c| c.starts_with("08") || TRANSIENT_SQLSTATES.contains(&&*c))
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Runs `attempt` until it succeeds or fails permanently, waiting longer between each try. Gives
+/// up with the last transient failure after a few tries.
+pub async fn retry<T, F, Fut>(what: &str, mut attempt: F) -> Result<T, SyncError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SyncError>>,
+{
+    let mut delay = RETRY_DELAY;
+    for _ in 1..RETRY_ATTEMPTS {
+        match attempt().await {
+            Err(SyncError::Transient(e)) => {
+                warn!("{what} failed, retrying in {delay:?}: {e}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    attempt().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{
+        error::ErrorKind as DatabaseErrorKind,
+        sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    };
+    use std::{
+        borrow::Cow,
+        error::Error,
+        io,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    /// A PostgreSQL-style error, with only a SQLSTATE.
+    #[derive(Debug)]
+    struct SqlState(&'static str);
+
+    impl fmt::Display for SqlState {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "SQLSTATE {}", self.0)
+        }
+    }
+
+    impl Error for SqlState {}
+
+    impl DatabaseError for SqlState {
+        fn message(&self) -> &str {
+            self.0
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> DatabaseErrorKind {
+            DatabaseErrorKind::Other
+        }
+    }
+
+    fn sql_state(code: &'static str) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(SqlState(code)))
+    }
+
+    fn io_error(kind: ErrorKind) -> sqlx::Error {
+        sqlx::Error::Io(io::Error::from(kind))
+    }
+
+    #[test]
+    fn io_errors() {
+        assert!(is_transient(&io_error(ErrorKind::TimedOut)));
+        assert!(is_transient(&io_error(ErrorKind::ConnectionReset)));
+        assert!(!is_transient(&io_error(ErrorKind::ConnectionRefused)));
+        assert!(!is_transient(&io_error(ErrorKind::PermissionDenied)));
+        assert!(matches!(
+            SyncError::from(io_error(ErrorKind::ConnectionRefused)),
+            SyncError::Permanent(ScriptoriumError::Unreachable { .. })
+        ));
+    }
+
+    #[test]
+    fn sql_states() {
+        for code in ["40001", "40P01", "57P01", "08006"] {
+            assert!(is_transient(&sql_state(code)), "{code}");
+        }
+        for code in ["28P01", "42P01", "23505"] {
+            assert!(!is_transient(&sql_state(code)), "{code}");
+        }
+        assert!(matches!(
+            SyncError::from(sql_state("28P01")),
+            SyncError::Permanent(ScriptoriumError::AuthFailed { .. })
+        ));
+        assert!(matches!(
+            SyncError::from(sql_state("40001")),
+            SyncError::Transient(ScriptoriumError::Database { .. })
+        ));
+        assert!(!is_auth_failure(&SqlState("42P01")));
+    }
+
+    #[test]
+    fn sqlite_errors() {
+        let path = std::env::temp_dir().join(format!("scriptorium-busy-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        tauri::async_runtime::block_on(async {
+            let options = SqliteConnectOptions::new()
+                .filename(&path)
+                .create_if_missing(true)
+                .busy_timeout(Duration::ZERO);
+            let pool = SqlitePoolOptions::new()
+                .max_connections(2)
+                .connect_with(options)
+                .await
+                .unwrap();
+            sqlx::query("CREATE TABLE t (x INTEGER)")
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let syntax = sqlx::query("SELEC 1").execute(&pool).await.unwrap_err();
+            assert!(!is_transient(&syntax));
+            assert!(matches!(
+                SyncError::from(syntax),
+                SyncError::Permanent(ScriptoriumError::Database { .. })
+            ));
+
+            let mut holding = pool.acquire().await.unwrap();
+            sqlx::query("BEGIN IMMEDIATE")
+                .execute(&mut *holding)
+                .await
+                .unwrap();
+            let busy = sqlx::query("INSERT INTO t (x) VALUES (1)")
+                .execute(&pool)
+                .await
+                .unwrap_err();
+            assert!(is_transient(&busy), "{busy}");
+            sqlx::query("ROLLBACK")
+                .execute(&mut *holding)
+                .await
+                .unwrap();
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn count_retries(outcomes: Vec<Result<(), SyncError>>) -> (Result<(), SyncError>, u32) {
+        let attempts = AtomicU32::new(0);
+        let result = tauri::async_runtime::block_on(retry("test", || {
+            let attempt = attempts.fetch_add(1, Ordering::Relaxed) as usize;
+            let outcome = outcomes[attempt.min(outcomes.len() - 1)].clone();
+            async move { outcome }
+        }));
+        (result, attempts.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn retries() {
+        let transient = || Err(SyncError::Transient("dropped".to_string().into()));
+        let permanent = || Err(SyncError::Permanent("denied".to_string().into()));
+
+        let (result, attempts) = count_retries(vec![transient(), transient(), Ok(())]);
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+
+        let (result, attempts) = count_retries(vec![permanent()]);
+        assert!(matches!(result, Err(SyncError::Permanent(_))));
+        assert_eq!(attempts, 1);
+
+        let (result, attempts) = count_retries(vec![transient()]);
+        assert!(matches!(result, Err(SyncError::Transient(_))));
+        assert_eq!(attempts, RETRY_ATTEMPTS);
+    }
+
+    // NOTE: src/errors.ts matches on these shapes
+    #[test]
+    fn serialized_shape() {
+        let json = |e: ScriptoriumError| serde_json::to_value(e).unwrap();
+        let message = || "failed".to_string();
+        let codes = [
+            (
+                ScriptoriumError::AuthFailed { message: message() },
+                "auth_failed",
+            ),
+            (
+                ScriptoriumError::TlsFailed { message: message() },
+                "tls_failed",
+            ),
+            (
+                ScriptoriumError::Unreachable { message: message() },
+                "unreachable",
+            ),
+            (
+                ScriptoriumError::SchemaMismatch { message: message() },
+                "schema_mismatch",
+            ),
+            (
+                ScriptoriumError::Encryption { message: message() },
+                "encryption",
+            ),
+            (
+                ScriptoriumError::Database { message: message() },
+                "database",
+            ),
+            (ScriptoriumError::Other { message: message() }, "other"),
+        ];
+        for (error, code) in codes {
+            assert_eq!(
+                json(error),
+                serde_json::json!({ "code": code, "message": "failed" })
+            );
+        }
+        assert_eq!(
+            json(ScriptoriumError::parse(message(), Some(3))),
+            serde_json::json!({ "code": "parse", "message": "failed", "line": 3 })
+        );
+        assert_eq!(
+            json(ScriptoriumError::parse(message(), None)),
+            serde_json::json!({ "code": "parse", "message": "failed", "line": null })
+        );
+    }
+}
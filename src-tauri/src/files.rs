@@ -0,0 +1,666 @@
+// Copyright (C) 2025  Athan Clark
+use crate::batches::{document_size, Batch, ID_CHUNK_SIZE};
+use crate::shares::{upsert_local_books, upsert_local_documents};
+use crate::types::{Book, Document, Id};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+use sqlx::{Pool, QueryBuilder, Sqlite};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+// NOTE: On-disk layout of a library:
+//
+//   <root>/.deleted                 -- permanently deleted ids, one per line, sorted
+//   <root>/<book id>/.book          -- front matter for the book
+//   <root>/<book id>/<doc id>.<ext> -- front matter for the document, followed by its content
+//
+// The extension is derived from the document's `syntax`.
+pub const BOOK_FILE: &str = ".book";
+const DELETED_FILE: &str = ".deleted";
+const FRONT_MATTER_FENCE: &str = "---";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Everything needed to reconstruct the database, including `modified` and tombstones
+    Repository,
+    /// Only what a person editing the files cares about -- timestamps come from the files
+    /// themselves, and deletions are made by removing files
+    Mirror,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Library {
+    pub books: Vec<Book>,
+    pub documents: Vec<Document>,
+    pub deleted: BTreeSet<String>,
+}
+
+/// Whether `id` could have been made by the database -- 32 lowercase hex digits, or one of the books
+/// every library has. Ids end up in paths, so anything else read from a directory is ignored.
+pub fn is_valid_id(id: &str) -> bool {
+    id == "default"
+        || id == "trash"
+        || (id.len() == 32 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))
+}
+
+fn check_ids<'a>(ids: impl IntoIterator<Item = &'a String>) -> Result<(), String> {
+    match ids.into_iter().find(|id| !is_valid_id(id)) {
+        Some(id) => Err(format!("invalid id: {id:?}")),
+        None => Ok(()),
+    }
+}
+
+pub fn syntax_extension(syntax: &str) -> &str {
+    match syntax {
+        "md" => "md",
+        "adoc" => "adoc",
+        "html" => "html",
+        _ => "txt",
+    }
+}
+
+pub fn extension_syntax(extension: &str) -> Option<&'static str> {
+    match extension {
+        "md" => Some("md"),
+        "adoc" => Some("adoc"),
+        "html" => Some("html"),
+        "txt" => Some("txt"),
+        _ => None,
+    }
+}
+
+fn format_modified(modified: &DateTime<Utc>) -> String {
+    modified.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn push_field(out: &mut String, key: &str, value: Value) {
+    out.push_str(key);
+    out.push_str(": ");
+    // NOTE: JSON scalars are valid YAML scalars, so the front matter stays readable by other tools
+    out.push_str(&value.to_string());
+    out.push('\n');
+}
+
+pub fn render_book(book: &Book, layout: Layout) -> String {
+    let mut out = String::from(FRONT_MATTER_FENCE);
+    out.push('\n');
+    push_field(&mut out, "name", book.name.clone().into());
+    push_field(&mut out, "icon", book.icon.clone().into());
+    push_field(&mut out, "icon_color", book.icon_color.clone().into());
+    push_field(&mut out, "trash", book.trash.into());
+    if layout == Layout::Repository {
+        push_field(&mut out, "modified", format_modified(&book.modified).into());
+    }
+    out.push_str(FRONT_MATTER_FENCE);
+    out.push('\n');
+    out
+}
+
+pub fn render_document(document: &Document, layout: Layout) -> String {
+    let mut out = String::from(FRONT_MATTER_FENCE);
+    out.push('\n');
+    push_field(&mut out, "name", document.name.clone().into());
+    push_field(&mut out, "icon", document.icon.clone().into());
+    push_field(&mut out, "icon_color", document.icon_color.clone().into());
+    push_field(&mut out, "syntax", document.syntax.clone().into());
+    if layout == Layout::Repository {
+        push_field(
+            &mut out,
+            "modified",
+            format_modified(&document.modified).into(),
+        );
+    }
+    out.push_str(FRONT_MATTER_FENCE);
+    out.push('\n');
+    if let Some(content) = &document.content {
+        out.push_str(content);
+    }
+    out
+}
+
+/// Splits a file into its front matter fields and the remaining body.
+pub fn parse_front_matter(text: &str) -> Result<(HashMap<String, Value>, &str), String> {
+    let rest = text
+        .strip_prefix(FRONT_MATTER_FENCE)
+        .and_then(|rest| rest.strip_prefix('\n'))
+        .ok_or_else(|| "missing front matter".to_string())?;
+    let mut fields = HashMap::new();
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        offset += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line == FRONT_MATTER_FENCE {
+            return Ok((fields, &rest[offset..]));
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("malformed front matter line: {line:?}"))?;
+        let value = value.trim();
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+        fields.insert(key.trim().to_string(), value);
+    }
+    Err("unterminated front matter".to_string())
+}
+
+fn optional_string(fields: &HashMap<String, Value>, key: &str) -> Option<String> {
+    fields.get(key).and_then(|v| v.as_str()).map(String::from)
+}
+
+fn parse_modified(
+    fields: &HashMap<String, Value>,
+    fallback: DateTime<Utc>,
+) -> Result<DateTime<Utc>, String> {
+    match optional_string(fields, "modified") {
+        None => Ok(fallback),
+        Some(modified) => DateTime::parse_from_rfc3339(&modified)
+            .map(|m| m.with_timezone(&Utc))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// `fallback_modified` is used when the front matter doesn't say when the book was modified.
+pub fn parse_book(id: &str, text: &str, fallback_modified: DateTime<Utc>) -> Result<Book, String> {
+    let (fields, _) = parse_front_matter(text)?;
+    Ok(Book {
+        id: id.to_string(),
+        modified: parse_modified(&fields, fallback_modified)?,
+        name: optional_string(&fields, "name"),
+        icon: optional_string(&fields, "icon"),
+        icon_color: optional_string(&fields, "icon_color"),
+        trash: fields.get("trash").and_then(|v| v.as_i64()).unwrap_or(0) as i32,
+    })
+}
+
+/// Files without front matter are taken to be entirely content, named after their id, so that
+/// documents can be created from other editors.
+pub fn parse_document(
+    id: &str,
+    book: &str,
+    extension: &str,
+    text: &str,
+    fallback_modified: DateTime<Utc>,
+) -> Result<Document, String> {
+    let (fields, body) = if text.starts_with(FRONT_MATTER_FENCE) {
+        parse_front_matter(text)?
+    } else {
+        (HashMap::from([("name".to_string(), Value::from(id))]), text)
+    };
+    let syntax = optional_string(&fields, "syntax")
+        .or_else(|| extension_syntax(extension).map(String::from))
+        .ok_or_else(|| format!("unknown syntax for document {id}"))?;
+    Ok(Document {
+        id: id.to_string(),
+        book: book.to_string(),
+        modified: parse_modified(&fields, fallback_modified)?,
+        name: optional_string(&fields, "name"),
+        content: Some(body.to_string()),
+        syntax,
+        icon: optional_string(&fields, "icon"),
+        icon_color: optional_string(&fields, "icon_color"),
+        content_hash: None,
+        rendered: None,
+    })
+}
+
+pub fn file_modified(path: &Path) -> DateTime<Utc> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now())
+}
+
+pub fn document_path(root: &Path, document: &Document) -> PathBuf {
+    root.join(&document.book).join(format!(
+        "{}.{}",
+        document.id,
+        syntax_extension(&document.syntax)
+    ))
+}
+
+fn is_document_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|e| extension_syntax(&e.to_string_lossy()))
+            .is_some()
+}
+
+/// Only touches the file when its content would actually change, so that file watchers and
+/// version control don't see spurious edits.
+fn write_if_changed(path: &Path, content: &str) -> Result<bool, String> {
+    match fs::read_to_string(path) {
+        Ok(existing) if existing == content => Ok(false),
+        _ => fs::write(path, content)
+            .map(|_| true)
+            .map_err(|e| format!("couldn't write {}: {e}", path.display())),
+    }
+}
+
+/// Writes the library out to `root`, removing any book directories or document files that are no
+/// longer part of it. Anything else in `root` is left alone. Unless `prune` is set, only the files
+/// of rows the library has or deleted are removed -- ones it never had are kept, as are the
+/// deletions already listed in `root`.
+pub fn write_library(
+    root: &Path,
+    library: &Library,
+    layout: Layout,
+    prune: bool,
+) -> Result<(), String> {
+    fs::create_dir_all(root).map_err(|e| e.to_string())?;
+
+    if layout == Layout::Repository {
+        let mut ids = library.deleted.clone();
+        if !prune {
+            if let Ok(existing) = fs::read_to_string(root.join(DELETED_FILE)) {
+                ids.extend(
+                    existing
+                        .lines()
+                        .map(str::trim)
+                        .filter(|id| !id.is_empty())
+                        .map(String::from),
+                );
+            }
+        }
+        let mut deleted = String::new();
+        for id in &ids {
+            deleted.push_str(id);
+            deleted.push('\n');
+        }
+        write_if_changed(&root.join(DELETED_FILE), &deleted)?;
+    }
+
+    let mut expected: HashMap<&str, HashSet<PathBuf>> = HashMap::new();
+    for book in &library.books {
+        let dir = root.join(&book.id);
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        write_if_changed(&dir.join(BOOK_FILE), &render_book(book, layout))?;
+        expected.entry(&book.id).or_default();
+    }
+    for document in &library.documents {
+        let path = document_path(root, document);
+        fs::create_dir_all(root.join(&document.book)).map_err(|e| e.to_string())?;
+        write_if_changed(&path, &render_document(document, layout))?;
+        expected.entry(&document.book).or_default().insert(path);
+    }
+
+    let known: HashSet<&str> = library
+        .books
+        .iter()
+        .map(|book| book.id.as_str())
+        .chain(
+            library
+                .documents
+                .iter()
+                .map(|document| document.id.as_str()),
+        )
+        .chain(library.deleted.iter().map(String::as_str))
+        .collect();
+    let stale = |id: &str| prune || known.contains(id);
+    for entry in fs::read_dir(root).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || !entry.path().join(BOOK_FILE).is_file() {
+            continue;
+        }
+        let files = expected.get(name.as_str());
+        if files.is_none() && stale(&name) {
+            fs::remove_dir_all(entry.path()).map_err(|e| e.to_string())?;
+            continue;
+        }
+        for file in fs::read_dir(entry.path()).map_err(|e| e.to_string())? {
+            let path = file.map_err(|e| e.to_string())?.path();
+            let id = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if is_document_file(&path)
+                && !files.is_some_and(|files| files.contains(&path))
+                && stale(&id)
+            {
+                fs::remove_file(&path).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_document(path: &Path, book: &str) -> Result<Document, String> {
+    let id = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_document(&id, book, &extension, &text, file_modified(path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Reads a library previously written with `write_library`. Directories without a `.book` file
+/// and files that aren't recognized documents, or aren't named by a valid id, are ignored.
+pub fn read_library(root: &Path) -> Result<Library, String> {
+    let mut library = Library::default();
+    if !root.exists() {
+        return Ok(library);
+    }
+
+    if let Ok(deleted) = fs::read_to_string(root.join(DELETED_FILE)) {
+        library.deleted = deleted
+            .lines()
+            .map(str::trim)
+            .filter(|l| is_valid_id(l))
+            .map(String::from)
+            .collect();
+    }
+
+    for entry in fs::read_dir(root).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let book_id = entry.file_name().to_string_lossy().to_string();
+        let book_file = entry.path().join(BOOK_FILE);
+        if !is_valid_id(&book_id) || !book_file.is_file() {
+            continue;
+        }
+        let text = fs::read_to_string(&book_file).map_err(|e| e.to_string())?;
+        library.books.push(
+            parse_book(&book_id, &text, file_modified(&book_file))
+                .map_err(|e| format!("{}: {e}", book_file.display()))?,
+        );
+
+        for file in fs::read_dir(entry.path()).map_err(|e| e.to_string())? {
+            let path = file.map_err(|e| e.to_string())?.path();
+            let id = path.file_stem().map(|s| s.to_string_lossy().to_string());
+            if is_document_file(&path) && id.is_some_and(|id| is_valid_id(&id)) {
+                library.documents.push(read_document(&path, &book_id)?);
+            }
+        }
+    }
+
+    Ok(library)
+}
+
+pub async fn read_local_library(local_conn: &Pool<Sqlite>) -> Result<Library, String> {
+    let books: Vec<Book> =
+        sqlx::query_as("SELECT id, name, modified, icon, icon_color, trash FROM books")
+            .fetch_all(local_conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    let documents: Vec<Document> = sqlx::query_as(
+        "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents",
+    )
+    .fetch_all(local_conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    let deleted: Vec<Id> = sqlx::query_as("SELECT id FROM deleted")
+        .fetch_all(local_conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Library {
+        books,
+        documents,
+        deleted: deleted.into_iter().map(|kv| kv.id).collect(),
+    })
+}
+
+/// Brings the local database up to date with `library`: rows that are missing or older locally
+/// are upserted, and ids listed as deleted are removed. Returns whether anything changed.
+pub async fn import_library(local_conn: &Pool<Sqlite>, library: Library) -> Result<bool, String> {
+    check_ids(library.books.iter().map(|b| &b.id))?;
+    check_ids(library.documents.iter().flat_map(|d| [&d.id, &d.book]))?;
+    check_ids(&library.deleted)?;
+    let mut has_modified = false;
+    let local = read_local_library(local_conn).await?;
+
+    let local_to_delete: Vec<&String> = library.deleted.difference(&local.deleted).collect();
+    if !local_to_delete.is_empty() {
+        has_modified = true;
+        // NOTE: Deleting rows populates `deleted` through triggers, so tombstones for ids that
+        // never existed locally are added afterwards
+        for ids in local_to_delete.chunks(ID_CHUNK_SIZE) {
+            for table in ["documents", "books"] {
+                let mut remove =
+                    QueryBuilder::<Sqlite>::new(format!("DELETE FROM {table} WHERE id IN ("));
+                let mut sep = remove.separated(", ");
+                for id in ids {
+                    sep.push_bind(*id);
+                }
+                sep.push_unseparated(")");
+                remove
+                    .build()
+                    .execute(local_conn)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+
+            let mut add_to_delete_table =
+                QueryBuilder::<Sqlite>::new("INSERT OR IGNORE INTO deleted (id)");
+            add_to_delete_table.push_values(ids, |mut builder, to_delete| {
+                builder.push_bind(*to_delete);
+            });
+            add_to_delete_table
+                .build()
+                .execute(local_conn)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    let deleted: HashSet<&String> = library.deleted.union(&local.deleted).collect();
+    let local_books: HashMap<&String, &DateTime<Utc>> =
+        local.books.iter().map(|b| (&b.id, &b.modified)).collect();
+    let local_documents: HashMap<&String, &DateTime<Utc>> = local
+        .documents
+        .iter()
+        .map(|d| (&d.id, &d.modified))
+        .collect();
+
+    let books: Vec<Book> = library
+        .books
+        .into_iter()
+        .filter(|b| !deleted.contains(&b.id))
+        .filter(|b| local_books.get(&b.id).is_none_or(|m| b.modified > **m))
+        .collect();
+    let book_ids: HashSet<&String> = local_books
+        .keys()
+        .copied()
+        .chain(books.iter().map(|b| &b.id))
+        .collect();
+    let documents: Vec<Document> = library
+        .documents
+        .into_iter()
+        .filter(|d| {
+            !deleted.contains(&d.id) && !deleted.contains(&d.book) && book_ids.contains(&d.book)
+        })
+        .filter(|d| local_documents.get(&d.id).is_none_or(|m| d.modified > **m))
+        .collect();
+
+    // NOTE: Written in batches, since SQLite limits how many parameters a query can bind
+    let mut batch = Batch::default();
+    for book in books {
+        has_modified = true;
+        if let Some(books) = batch.push(book, 0) {
+            upsert_local_books(local_conn, books)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    if let Some(books) = batch.finish() {
+        upsert_local_books(local_conn, books)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    let mut batch = Batch::default();
+    for document in documents {
+        has_modified = true;
+        let size = document_size(&document);
+        if let Some(documents) = batch.push(document, size) {
+            upsert_local_documents(local_conn, documents)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    if let Some(documents) = batch.finish() {
+        upsert_local_documents(local_conn, documents)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(has_modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::SQLITE_MIGRATIONS;
+    use sqlx::{migrate::Migrator, sqlite::SqlitePoolOptions};
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("scriptorium-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    fn id(n: usize) -> String {
+        format!("{n:032x}")
+    }
+
+    fn book(id: &str) -> Book {
+        Book {
+            id: id.to_string(),
+            modified: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            name: Some("Runbooks: \"prod\"".to_string()),
+            icon: Some("book".to_string()),
+            icon_color: None,
+            trash: 0,
+        }
+    }
+
+    fn document(id: &str, book: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            book: book.to_string(),
+            modified: DateTime::from_timestamp(1_700_000_000, 123_456_000).unwrap(),
+            name: Some("Notes".to_string()),
+            content: Some("# Title\n---\nnot front matter\n".to_string()),
+            syntax: "md".to_string(),
+            icon: None,
+            icon_color: Some("#ff0000".to_string()),
+            content_hash: None,
+            rendered: None,
+        }
+    }
+
+    #[test]
+    fn valid_ids() {
+        assert!(is_valid_id("default"));
+        assert!(is_valid_id("trash"));
+        assert!(is_valid_id(&id(42)));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id(".."));
+        assert!(!is_valid_id("notes"));
+        assert!(!is_valid_id(&id(42).to_uppercase()));
+        assert!(!is_valid_id(&format!("{}/", &id(42)[1..])));
+        assert!(!is_valid_id(&format!("{}0", id(42))));
+    }
+
+    #[test]
+    fn front_matter_round_trip() {
+        let book = book(&id(1));
+        let parsed = parse_book(
+            &book.id,
+            &render_book(&book, Layout::Repository),
+            Utc::now(),
+        );
+        assert_eq!(format!("{:?}", parsed.unwrap()), format!("{book:?}"));
+
+        let document = document(&id(2), &book.id);
+        let text = render_document(&document, Layout::Repository);
+        let (fields, body) = parse_front_matter(&text).unwrap();
+        assert_eq!(fields["syntax"], "md");
+        assert_eq!(body, document.content.as_deref().unwrap());
+        let parsed = parse_document(&document.id, &book.id, "md", &text, Utc::now());
+        assert_eq!(format!("{:?}", parsed.unwrap()), format!("{document:?}"));
+    }
+
+    #[test]
+    fn mirror_front_matter_leaves_out_modified() {
+        let document = document(&id(2), "default");
+        let fallback = DateTime::from_timestamp(1_800_000_000, 0).unwrap();
+        let text = render_document(&document, Layout::Mirror);
+        let parsed = parse_document(&document.id, "default", "md", &text, fallback).unwrap();
+        assert_eq!(parsed.modified, fallback);
+    }
+
+    #[test]
+    fn bad_front_matter() {
+        assert!(parse_front_matter("no fence").is_err());
+        assert!(parse_front_matter("---\nname: \"x\"\n").is_err());
+        assert!(parse_front_matter("---\nno separator\n---\n").is_err());
+    }
+
+    #[test]
+    fn library_round_trip() {
+        let root = temp_root("library");
+        let library = Library {
+            books: vec![book(&id(1)), book("default")],
+            documents: vec![document(&id(2), "default"), document(&id(3), &id(1))],
+            deleted: BTreeSet::from([id(4), id(5)]),
+        };
+        write_library(&root, &library, Layout::Repository, true).unwrap();
+        fs::write(root.join("default").join("notes.md"), "# ignored").unwrap();
+        fs::create_dir_all(root.join("not-a-book")).unwrap();
+        fs::write(root.join("not-a-book").join(BOOK_FILE), "---\n---\n").unwrap();
+
+        let mut read = read_library(&root).unwrap();
+        read.books.sort_by(|a, b| a.id.cmp(&b.id));
+        read.documents.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(format!("{read:?}"), format!("{library:?}"));
+
+        // NOTE: Pruning removes what the library no longer has
+        let smaller = Library {
+            books: vec![book("default")],
+            documents: vec![document(&id(2), "default")],
+            deleted: library.deleted.clone(),
+        };
+        write_library(&root, &smaller, Layout::Repository, true).unwrap();
+        assert!(!root.join(id(1)).exists());
+        assert_eq!(read_library(&root).unwrap().documents.len(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn import_many_rows() {
+        tauri::async_runtime::block_on(async {
+            let local_conn = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            Migrator::new(SQLITE_MIGRATIONS.clone())
+                .await
+                .unwrap()
+                .run(&local_conn)
+                .await
+                .unwrap();
+            // NOTE: More rows than SQLite can bind parameters for in one statement
+            let count = 5000;
+            let library = Library {
+                books: vec![book("default")],
+                documents: (0..count).map(|n| document(&id(n), "default")).collect(),
+                deleted: (count..2 * count).map(id).collect(),
+            };
+            assert!(import_library(&local_conn, library).await.unwrap());
+            let imported = read_local_library(&local_conn).await.unwrap();
+            assert_eq!(imported.documents.len(), count);
+            assert_eq!(imported.deleted.len(), count);
+        });
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright (C) 2025  Athan Clark
+use crate::files::{
+    import_library, parse_front_matter, read_library, read_local_library, write_library, Layout,
+    Library,
+};
+use crate::proxy::{Proxy, ProxyKind};
+use crate::types::{Direction, RemoteServer};
+use git2::{
+    build::CheckoutBuilder, Cred, CredentialType, FetchOptions, IndexAddOption, IndexEntry,
+    ProxyOptions, PushOptions, RemoteCallbacks, Repository, RepositoryInitOptions, Signature,
+};
+use log::{debug, warn};
+use sqlx::{Pool, Sqlite};
+use std::{
+    cell::Cell,
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+use tauri::{AppHandle, Manager};
+
+// NOTE: For git remotes, `RemoteServer::host` holds the repository URL (or the path to a local
+// bare repository) and `RemoteServer::db` holds the branch name.
+const REMOTE_NAME: &str = "origin";
+const DEFAULT_BRANCH: &str = "main";
+const DELETED_PATH: &str = ".deleted";
+// NOTE: libgit2's `GIT_INDEX_ENTRY_STAGEMASK`
+const INDEX_ENTRY_STAGE_MASK: u16 = 0x3000;
+
+/// Each git remote gets its own working copy in the app's config directory.
+pub fn work_dir(app: &AppHandle, remote: &RemoteServer) -> Result<PathBuf, String> {
+    let mut dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    dir.push("git");
+    dir.push(&remote.id);
+    Ok(dir)
+}
+
+fn branch_name(remote: &RemoteServer) -> &str {
+    if remote.db.is_empty() {
+        DEFAULT_BRANCH
+    } else {
+        &remote.db
+    }
+}
+
+fn remote_callbacks(remote: &RemoteServer) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    let attempts = Cell::new(0);
+    callbacks.credentials(move |_url, username_from_url, allowed| {
+        // NOTE: libgit2 keeps asking for credentials as long as they're rejected
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > 3 {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        let user = username_from_url.unwrap_or(&remote.user);
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !remote.password.is_empty() {
+            Cred::userpass_plaintext(&remote.user, &remote.password)
+        } else if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(user)
+        } else if allowed.contains(CredentialType::USERNAME) {
+            Cred::username(user)
+        } else {
+            Cred::default()
+        }
+    });
+    callbacks
+}
+
+// NOTE: libgit2 only speaks HTTP to proxies, and would send its CONNECT request to a SOCKS one
+fn proxy_options(remote: &RemoteServer) -> Result<ProxyOptions<'_>, git2::Error> {
+    let mut options = ProxyOptions::new();
+    if let Some(proxy) = &remote.proxy {
+        if Proxy::parse(proxy)
+            .map_err(|e| git2::Error::from_str(&e))?
+            .kind
+            == ProxyKind::Socks5
+        {
+            return Err(git2::Error::from_str(
+                "git remotes can't go through a SOCKS proxy -- use an HTTP proxy instead",
+            ));
+        }
+        options.url(proxy);
+    }
+    Ok(options)
+}
+
+fn open_repository(work_dir: &Path, remote: &RemoteServer) -> Result<Repository, git2::Error> {
+    let repo = match Repository::open(work_dir) {
+        Ok(repo) => repo,
+        Err(_) => {
+            let mut options = RepositoryInitOptions::new();
+            options.initial_head(branch_name(remote));
+            Repository::init_opts(work_dir, &options)?
+        }
+    };
+    match repo.find_remote(REMOTE_NAME) {
+        Ok(existing) if existing.url() == Some(remote.host.as_str()) => {}
+        Ok(_) => repo.remote_set_url(REMOTE_NAME, &remote.host)?,
+        Err(_) => {
+            repo.remote(REMOTE_NAME, &remote.host)?;
+        }
+    }
+    Ok(repo)
+}
+
+fn signature(repo: &Repository) -> Result<Signature<'static>, git2::Error> {
+    repo.signature()
+        .or_else(|_| Signature::now("Scriptorium", "scriptorium@localhost"))
+}
+
+/// Commits the whole working tree if it differs from `HEAD`. Returns whether a commit was made.
+fn commit_all(repo: &Repository, message: &str) -> Result<bool, git2::Error> {
+    let mut index = repo.index()?;
+    index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"].iter(), None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    if let Some(parent) = &parent {
+        if parent.tree_id() == tree.id() {
+            return Ok(false);
+        }
+    }
+    let sig = signature(repo)?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)?;
+    Ok(true)
+}
+
+fn entry_modified(repo: &Repository, entry: &IndexEntry) -> Option<String> {
+    let blob = repo.find_blob(entry.id).ok()?;
+    let text = std::str::from_utf8(blob.content()).ok()?;
+    let (fields, _) = parse_front_matter(text).ok()?;
+    fields.get("modified")?.as_str().map(String::from)
+}
+
+fn union_deleted(repo: &Repository, entries: &[&IndexEntry]) -> Result<Vec<u8>, git2::Error> {
+    let mut ids = BTreeSet::new();
+    for entry in entries {
+        let blob = repo.find_blob(entry.id)?;
+        for id in String::from_utf8_lossy(blob.content()).lines() {
+            if !id.trim().is_empty() {
+                ids.insert(id.trim().to_string());
+            }
+        }
+    }
+    let mut out = String::new();
+    for id in ids {
+        out.push_str(&id);
+        out.push('\n');
+    }
+    Ok(out.into_bytes())
+}
+
+/// Resolves every conflict in a merge index: tombstone lists are unioned, and for books and
+/// documents the side with the newest `modified` wins. When one side removed a file, the other
+/// side's copy is kept, since deletions are carried by the tombstone list instead.
+fn resolve_conflicts(repo: &Repository, index: &mut git2::Index) -> Result<(), git2::Error> {
+    let conflicts = index
+        .conflicts()?
+        .collect::<Result<Vec<_>, git2::Error>>()?;
+    for conflict in conflicts {
+        let sides: Vec<&IndexEntry> = [conflict.our.as_ref(), conflict.their.as_ref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        let Some(first) = sides.first() else {
+            continue;
+        };
+        let path = String::from_utf8_lossy(&first.path).to_string();
+        debug!("resolving conflict in {path}");
+
+        let mut resolved = IndexEntry {
+            ctime: first.ctime,
+            mtime: first.mtime,
+            dev: first.dev,
+            ino: first.ino,
+            mode: first.mode,
+            uid: first.uid,
+            gid: first.gid,
+            file_size: first.file_size,
+            id: first.id,
+            flags: first.flags & !INDEX_ENTRY_STAGE_MASK,
+            flags_extended: first.flags_extended,
+            path: first.path.clone(),
+        };
+        if path == DELETED_PATH {
+            let content = union_deleted(repo, &sides)?;
+            resolved.id = repo.blob(&content)?;
+            resolved.file_size = content.len() as u32;
+        } else if let [ours, theirs] = sides[..] {
+            if entry_modified(repo, theirs) > entry_modified(repo, ours) {
+                resolved.id = theirs.id;
+                resolved.file_size = theirs.file_size;
+                resolved.mode = theirs.mode;
+            }
+        }
+
+        index.conflict_remove(Path::new(&path))?;
+        index.add(&resolved)?;
+    }
+    Ok(())
+}
+
+/// Merges the fetched branch into `HEAD`. Returns whether `HEAD` changed.
+fn merge_fetched(repo: &Repository, branch: &str) -> Result<bool, git2::Error> {
+    let Ok(fetched) = repo.find_reference(&format!("refs/remotes/{REMOTE_NAME}/{branch}")) else {
+        // NOTE: The remote doesn't have the branch yet
+        return Ok(false);
+    };
+    let annotated = repo.reference_to_annotated_commit(&fetched)?;
+    let (analysis, _) = repo.merge_analysis(&[&annotated])?;
+    let local_ref = format!("refs/heads/{branch}");
+
+    if analysis.is_up_to_date() {
+        Ok(false)
+    } else if analysis.is_fast_forward() || analysis.is_unborn() {
+        repo.reference(
+            &local_ref,
+            annotated.id(),
+            true,
+            "scriptorium: fast-forward",
+        )?;
+        repo.set_head(&local_ref)?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+        Ok(true)
+    } else {
+        let ours = repo.head()?.peel_to_commit()?;
+        let theirs = repo.find_commit(annotated.id())?;
+        let mut index = repo.merge_commits(&ours, &theirs, None)?;
+        if index.has_conflicts() {
+            resolve_conflicts(repo, &mut index)?;
+        }
+        let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+        let sig = signature(repo)?;
+        repo.commit(
+            Some("HEAD"),
+            &sig,
+            &sig,
+            "Merge remote changes",
+            &tree,
+            &[&ours, &theirs],
+        )?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+        Ok(true)
+    }
+}
+
+/// Pushes `branch`, replacing whatever the remote has for it when `force` is set.
+fn push(
+    repo: &Repository,
+    remote: &RemoteServer,
+    branch: &str,
+    force: bool,
+) -> Result<(), git2::Error> {
+    let mut rejection: Option<String> = None;
+    {
+        let mut callbacks = remote_callbacks(remote);
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(status) = status {
+                rejection = Some(format!("push of {refname} rejected: {status}"));
+            }
+            Ok(())
+        });
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        options.proxy_options(proxy_options(remote)?);
+        let force = if force { "+" } else { "" };
+        let refspec = format!("{force}refs/heads/{branch}:refs/heads/{branch}");
+        repo.find_remote(REMOTE_NAME)?
+            .push(&[refspec.as_str()], Some(&mut options))?;
+    }
+    match rejection {
+        Some(e) => Err(git2::Error::from_str(&e)),
+        None => Ok(()),
+    }
+}
+
+/// Commits `library` into the working copy at `work_dir`, merges in the remote branch, and pushes
+/// the result. Returns the merged library, and whether anything was committed locally. Nothing is
+/// committed or pushed unless `direction` pushes, and when it overwrites, nothing is fetched -- the
+/// remote branch is replaced with the local one.
+fn sync_repository(
+    work_dir: &Path,
+    remote: &RemoteServer,
+    library: &Library,
+    direction: Direction,
+) -> Result<(Library, bool), String> {
+    let branch = branch_name(remote);
+    let repo = open_repository(work_dir, remote).map_err(|e| e.to_string())?;
+
+    let committed = if direction.pushes() {
+        // NOTE: The work tree has the remote's files merged in, which a push-only remote keeps
+        let prune = direction.pulls() || direction.overwrites();
+        write_library(work_dir, library, Layout::Repository, prune)?;
+        commit_all(&repo, "Update library").map_err(|e| e.to_string())?
+    } else {
+        false
+    };
+
+    if direction.overwrites() {
+        push(&repo, remote, branch, true).map_err(|e| e.to_string())?;
+        debug!("overwrote {branch} on {}", remote.host);
+        return Ok((library.clone(), committed));
+    }
+
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(remote_callbacks(remote));
+    options.proxy_options(proxy_options(remote).map_err(|e| e.to_string())?);
+    let refspec = format!("refs/heads/{branch}:refs/remotes/{REMOTE_NAME}/{branch}");
+    repo.find_remote(REMOTE_NAME)
+        .and_then(|mut r| r.fetch(&[refspec.as_str()], Some(&mut options), None))
+        .map_err(|e| e.to_string())?;
+    debug!("fetched {branch} from {}", remote.host);
+
+    let merged = merge_fetched(&repo, branch).map_err(|e| e.to_string())?;
+    if direction.pushes() && (committed || merged) {
+        push(&repo, remote, branch, false).map_err(|e| e.to_string())?;
+        debug!("pushed {branch} to {}", remote.host);
+    }
+
+    Ok((read_library(work_dir)?, committed))
+}
+
+pub async fn actually_sync_databases_git(
+    local_conn: &Pool<Sqlite>,
+    remote: &RemoteServer,
+    work_dir: PathBuf,
+    direction: Direction,
+) -> Result<bool, String> {
+    let library = read_local_library(local_conn).await?;
+    let remote = remote.clone();
+    let (merged, committed) = tauri::async_runtime::spawn_blocking(move || {
+        sync_repository(&work_dir, &remote, &library, direction)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    let imported = direction.pulls() && import_library(local_conn, merged).await?;
+    Ok(committed || imported) // NOTE: return if changes were made
+}
+
+/// Makes sure the working copy exists and that the remote repository is reachable.
+pub async fn check_git_remote(remote: &RemoteServer, work_dir: PathBuf) -> Result<(), String> {
+    let remote = remote.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let repo = open_repository(&work_dir, &remote).map_err(|e| e.to_string())?;
+        let mut origin = repo.find_remote(REMOTE_NAME).map_err(|e| e.to_string())?;
+        let mut connection = origin
+            .connect_auth(
+                git2::Direction::Fetch,
+                Some(remote_callbacks(&remote)),
+                Some(proxy_options(&remote).map_err(|e| e.to_string())?),
+            )
+            .map_err(|e| e.to_string())?;
+        if !connection.connected() {
+            warn!("couldn't connect to {}", remote.host);
+            return Err(format!("couldn't connect to {}", remote.host));
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Book, Document};
+    use chrono::DateTime;
+    use std::fs;
+
+    const DOCUMENT_PATH: &str = "default/0000000000000000000000000000000d.md";
+
+    fn library(document_modified: i64, content: &str, deleted: &[&str]) -> Library {
+        Library {
+            books: vec![Book {
+                id: "default".to_string(),
+                modified: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+                name: Some("Default".to_string()),
+                icon: None,
+                icon_color: None,
+                trash: 0,
+            }],
+            documents: vec![Document {
+                id: "0000000000000000000000000000000d".to_string(),
+                book: "default".to_string(),
+                modified: DateTime::from_timestamp(document_modified, 0).unwrap(),
+                name: Some("Notes".to_string()),
+                content: Some(content.to_string()),
+                syntax: "md".to_string(),
+                icon: None,
+                icon_color: None,
+                content_hash: None,
+                rendered: None,
+            }],
+            deleted: deleted.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    /// Commits `library` on top of `parent`, without moving any branch.
+    fn commit(repo: &Repository, parent: Option<git2::Oid>, library: &Library) -> git2::Oid {
+        if let Some(parent) = parent {
+            repo.set_head_detached(parent).unwrap();
+            repo.checkout_head(Some(CheckoutBuilder::new().force()))
+                .unwrap();
+        }
+        write_library(repo.workdir().unwrap(), library, Layout::Repository, true).unwrap();
+        commit_all(repo, "test").unwrap();
+        repo.head().unwrap().peel_to_commit().unwrap().id()
+    }
+
+    fn resolved(repo: &Repository, index: &git2::Index, path: &str) -> String {
+        let entry = index.get_path(Path::new(path), 0).unwrap();
+        let blob = repo.find_blob(entry.id).unwrap();
+        String::from_utf8(blob.content().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn newest_side_wins() {
+        let root = std::env::temp_dir().join(format!("scriptorium-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let repo = Repository::init(&root).unwrap();
+        let base = commit(&repo, None, &library(1_700_000_000, "base", &[]));
+        let a = "0000000000000000000000000000000a";
+        let b = "0000000000000000000000000000000b";
+        let ours = commit(&repo, Some(base), &library(1_700_000_200, "ours", &[a]));
+        let theirs = commit(&repo, Some(base), &library(1_700_000_100, "theirs", &[b]));
+
+        let ours = repo.find_commit(ours).unwrap();
+        let theirs = repo.find_commit(theirs).unwrap();
+        let mut index = repo.merge_commits(&ours, &theirs, None).unwrap();
+        assert!(index.has_conflicts());
+        resolve_conflicts(&repo, &mut index).unwrap();
+        assert!(!index.has_conflicts());
+        assert!(resolved(&repo, &index, DOCUMENT_PATH).ends_with("ours"));
+        assert_eq!(resolved(&repo, &index, DELETED_PATH), format!("{a}\n{b}\n"));
+
+        // NOTE: Whichever side it's on
+        let mut index = repo.merge_commits(&theirs, &ours, None).unwrap();
+        resolve_conflicts(&repo, &mut index).unwrap();
+        assert!(resolved(&repo, &index, DOCUMENT_PATH).ends_with("ours"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
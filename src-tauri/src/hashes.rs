@@ -0,0 +1,93 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::SyncError;
+use crate::types::{Book, Document};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+
+const HASH_PAGE_SIZE: i64 = 100;
+
+/// Hashes every synced field of a document besides `modified`, so a row whose timestamp moved
+/// without its content changing can be told apart from an edited one.
+pub fn document_hash(document: &Document) -> String {
+    hash_fields([
+        Some(&document.book),
+        document.name.as_ref(),
+        document.content.as_ref(),
+        Some(&document.syntax),
+        document.icon.as_ref(),
+        document.icon_color.as_ref(),
+    ])
+}
+
+/// Hashes every synced field of a book besides `modified`, like `document_hash`.
+pub fn book_hash(book: &Book) -> String {
+    hash_fields([
+        book.name.as_ref(),
+        book.icon.as_ref(),
+        book.icon_color.as_ref(),
+        Some(&book.trash.to_string()),
+    ])
+}
+
+fn hash_fields<'a>(fields: impl IntoIterator<Item = Option<&'a String>>) -> String {
+    let mut hasher = Sha256::new();
+    for field in fields {
+        // NOTE: Length-prefixed, so text moving from one field to the next changes the hash
+        match field {
+            None => hasher.update([0]),
+            Some(value) => {
+                hasher.update([1]);
+                hasher.update((value.len() as u64).to_le_bytes());
+                hasher.update(value.as_bytes());
+            }
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Whether both sides of a document hold the same fields. Documents without a hash never match.
+pub fn same_content(a: &Option<String>, b: &Option<String>) -> bool {
+    matches!((a, b), (Some(a), Some(b)) if a == b)
+}
+
+/// Hashes the local documents that don't have a hash yet -- a trigger clears it whenever one of
+/// their synced fields is edited.
+pub async fn hash_local_documents(local_conn: &Pool<Sqlite>) -> Result<(), SyncError> {
+    // NOTE: A page at a time, since these are written to the table being read -- each page's rows
+    // aren't selected again once they're hashed
+    loop {
+        let documents: Vec<Document> = sqlx::query_as(
+            "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE content_hash IS NULL LIMIT ?",
+        )
+        .bind(HASH_PAGE_SIZE)
+        .fetch_all(local_conn)
+        .await?;
+        if documents.is_empty() {
+            return Ok(());
+        }
+        for document in documents {
+            sqlx::query("UPDATE documents SET content_hash = ? WHERE id = ?")
+                .bind(document_hash(&document))
+                .bind(&document.id)
+                .execute(local_conn)
+                .await?;
+        }
+    }
+}
+
+/// Moves local documents' modification times forward to the remote's, for documents that are
+/// otherwise identical on both sides.
+pub async fn reconcile_local_documents(
+    local_conn: &Pool<Sqlite>,
+    documents: &[(String, DateTime<Utc>)],
+) -> Result<(), SyncError> {
+    for (id, modified) in documents {
+        sqlx::query("UPDATE documents SET modified = ? WHERE id = ?")
+            .bind(modified)
+            .bind(id)
+            .execute(local_conn)
+            .await?;
+    }
+    Ok(())
+}
@@ -1,12 +1,60 @@
 // Copyright (C) 2025  Athan Clark
 mod types;
-use crate::types::{RemoteServer, ValueString};
+use crate::types::{Direction, Grant, RemoteServer, Snapshot, ValueString};
+use crate::verify::Divergence;
+mod batches;
+mod compression;
+mod crypto;
+mod error;
+use crate::crypto::EncryptionState;
+use crate::error::{retry, ScriptoriumError, SyncError};
+mod connection;
+use crate::connection::{
+    connection_url, mysql_connect_options, parse_connection_url, pg_connect_options,
+};
+mod credentials;
+use crate::credentials::{
+    resolve_password, resolve_proxy, save_proxy as save_proxy_setting,
+    save_remote_server as save_remote_server_settings, unlock_credentials, RemoteServerSettings,
+};
 mod mysql;
-use crate::mysql::actually_sync_databases_mysql;
+use crate::mysql::{
+    actually_sync_databases_mysql, book_grants_mysql, check_schema_mysql, create_snapshot_mysql,
+    publish_books_mysql, remote_compression_mysql, remote_key_mysql, restore_snapshot_mysql,
+    set_remote_compression_mysql, share_book_mysql, snapshots_mysql, unshare_book_mysql,
+    verify_remote_mysql,
+};
 mod postgres;
-use crate::postgres::actually_sync_databases_postgres;
+use crate::postgres::{
+    actually_sync_databases_postgres, book_grants_postgres, check_schema_postgres,
+    create_schema_postgres, create_snapshot_postgres, publish_books_postgres,
+    remote_compression_postgres, remote_key_postgres, restore_snapshot_postgres,
+    set_remote_compression_postgres, share_book_postgres, snapshots_postgres,
+    unshare_book_postgres, verify_remote_postgres,
+};
+mod files;
+mod git;
+mod hashes;
+mod merkle;
+mod schema;
+mod shares;
+mod snapshots;
+use crate::git::{actually_sync_databases_git, check_git_remote};
+use crate::shares::check_share;
+use crate::snapshots::{check_snapshot_name, check_snapshot_remote};
+mod mirror;
+mod outbox;
+use crate::mirror::{start_mirror, stop_mirror, MirrorState, MIRROR_DIRECTORY_SETTING};
+use crate::outbox::{acknowledge_outbox, forget_outbox, read_outbox};
+mod proxy;
+mod publish;
+mod tunnel;
+mod verify;
+use crate::tunnel::{open_tunnel, Tunnel};
 mod migrations;
-use crate::migrations::{MYSQL_MIGRATIONS, PG_MIGRATIONS, SQLITE_MIGRATIONS};
+use crate::migrations::{
+    prefixed_mysql_migrations, MYSQL_MIGRATIONS, PG_MIGRATIONS, SQLITE_MIGRATIONS,
+};
 
 use asciidocr as adoc;
 use chrono::{DateTime, Utc};
@@ -15,13 +63,14 @@ use log::{debug, info, warn};
 use pulldown_cmark as md;
 use sqlx::{
     migrate::{Migrate, Migrator},
-    mysql::{MySqlConnectOptions, MySqlPool, MySqlSslMode},
+    mysql::MySqlPool,
     pool::PoolOptions,
-    postgres::{PgConnectOptions, PgPool, PgSslMode},
+    postgres::PgPool,
     ConnectOptions, Connection, Database, MySql, Pool, Postgres, QueryBuilder, Sqlite,
 };
-use std::{str::FromStr, time::Duration};
+use std::{future::Future, str::FromStr, time::Duration};
 use tauri::{
+    AppHandle,
     State,
     // menu::{Menu, Submenu, MenuItem}
 };
@@ -50,14 +99,17 @@ fn render_md(value: &str) -> String {
 }
 
 #[tauri::command]
-fn render_adoc(value: &str) -> Result<String, String> {
+fn render_adoc(value: &str) -> Result<String, ScriptoriumError> {
     let scanner = adoc::scanner::Scanner::new(value);
     let mut path = std::path::PathBuf::new();
     path.push(r".");
     let mut parser = adoc::parser::Parser::new(path);
-    let asg = parser.parse(scanner).map_err(|e| e.to_string())?;
+    let asg = parser
+        .parse(scanner)
+        .map_err(|e| ScriptoriumError::parse(e.to_string(), Some(e.line)))?;
     info!("completed parsing");
-    let res = adoc::backends::htmls::render_htmlbook(&asg).map_err(|e| e.to_string());
+    let res = adoc::backends::htmls::render_htmlbook(&asg)
+        .map_err(|e| ScriptoriumError::parse(e.to_string(), None));
     info!("completed rendering");
     res
 }
@@ -67,12 +119,16 @@ const DEFAULT_AUTO_SYNC_TIME: u32 = 60;
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
-async fn sync_databases(db_instances: State<'_, DbInstances>) -> Result<(), Vec<String>> {
+async fn sync_databases(
+    app: AppHandle,
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+) -> Result<(), Vec<ScriptoriumError>> {
     let instances = db_instances.0.read().await;
 
     let db = instances
         .get("sqlite:scriptorium.db")
-        .ok_or_else(|| vec!["database not loaded".to_string()])?;
+        .ok_or_else(|| vec!["database not loaded".to_string().into()])?;
 
     match db {
         DbPool::Sqlite(local_conn) => {
@@ -80,22 +136,45 @@ async fn sync_databases(db_instances: State<'_, DbInstances>) -> Result<(), Vec<
                 sqlx::query_as("SELECT value FROM settings WHERE key = 'auto_sync_time'")
                     .fetch_all(local_conn)
                     .await
-                    .map_err(|e| vec![e.to_string()])
+                    .map_err(|e| vec![e.into()])
                     .and_then(|vs: Vec<ValueString>| {
                         if vs.len() > 0 {
-                            u32::from_str(&vs[0].value).map_err(|e| vec![e.to_string()])
+                            u32::from_str(&vs[0].value).map_err(|e| vec![e.to_string().into()])
                         } else {
                             Ok(5)
                         }
                     })?;
-            let mut saved_dbs: Vec<RemoteServer> = sqlx::query_as(
-                "SELECT id, host, port, db, user, password, db_type FROM remote_servers",
+            let stored_dbs: Vec<RemoteServer> = sqlx::query_as(
+                "SELECT id, host, port, db, user, password, password_command, db_type, ssl_mode, ssl_ca, ssl_client_cert, ssl_client_key, ssh_host, ssh_user, ssh_key, proxy, namespace, direction, publish, is_primary, encrypt FROM remote_servers",
             )
             .fetch_all(local_conn)
             .await
-            .map_err(|e| vec![e.to_string()])?;
+            .map_err(|e| vec![e.into()])?;
+            // NOTE: The primary is synced first, so the secondaries get the state resolved with it
+            let has_primary = stored_dbs.iter().any(|s| s.is_primary);
+            let mut primary_synced = false;
+            let passphrase = encryption.passphrase();
+            let credentials_key = encryption.credentials_key();
             let mut changes_made = false;
-            let mut errors: Vec<String> = vec![];
+            let mut errors: Vec<ScriptoriumError> = vec![];
+            let mut saved_dbs: Vec<RemoteServer> = vec![];
+            for mut saved_db in stored_dbs {
+                let resolved = match resolve_password(&saved_db, credentials_key.as_ref()).await {
+                    Ok(password) => resolve_proxy(local_conn, &saved_db, credentials_key.as_ref())
+                        .await
+                        .map(|proxy| (password, proxy)),
+                    Err(e) => Err(e),
+                };
+                match resolved {
+                    Ok((password, proxy)) => {
+                        saved_db.password = password;
+                        saved_db.proxy = proxy;
+                        saved_dbs.push(saved_db);
+                    }
+                    Err(e) => errors.push(e.into()),
+                }
+            }
+            saved_dbs.sort_by_key(|s| !s.is_primary);
             let mut idx = 0;
 
             loop {
@@ -117,82 +196,110 @@ async fn sync_databases(db_instances: State<'_, DbInstances>) -> Result<(), Vec<
                 }
 
                 let saved_db = &saved_dbs[idx];
+                let direction = match saved_db.sync_direction(has_primary) {
+                    Ok(direction) => direction,
+                    Err(e) => {
+                        errors.push(e.into());
+                        saved_dbs.remove(idx);
+                        continue;
+                    }
+                };
+                if direction.overwrites() && !primary_synced && !saved_db.publish {
+                    warn!("not syncing {}, the primary didn't sync", saved_db.host);
+                    saved_dbs.remove(idx);
+                    continue;
+                }
 
-                match saved_db.db_type.as_str() {
-                    "mysql" => {
-                        let conn_options = MySqlConnectOptions::new()
-                            .host(&saved_db.host)
-                            .username(&saved_db.user)
-                            .password(&saved_db.password)
-                            .port(saved_db.port)
-                            .database(&saved_db.db)
-                            .ssl_mode(MySqlSslMode::Required);
-                        let e_conn: Result<MySqlPool, String> = run_migrations_and_get_pool(
-                            conn_options,
-                            MYSQL_MIGRATIONS.clone(),
-                            auto_sync_time,
-                        )
-                        .await;
-                        info!("e_conn returned");
-                        match e_conn {
-                            Ok(conn) => {
-                                let e_caused_changes =
-                                    actually_sync_databases_mysql(&local_conn, &conn).await;
-                                match e_caused_changes {
-                                    Err(e) => {
-                                        errors.push(e);
-                                    }
-                                    Ok(caused_changes) => {
-                                        changes_made = caused_changes || changes_made;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                errors.push(e.clone());
-                                saved_dbs.remove(idx);
-                                warn!("error, {e:?}, removing {idx}");
-                            }
+                let e_caused_changes = match saved_db.db_type.as_str() {
+                    "mysql" if saved_db.publish => Some(
+                        retry(&format!("publishing to {}", saved_db.host), || {
+                            publish_remote_mysql(local_conn, saved_db, auto_sync_time)
+                        })
+                        .await,
+                    ),
+                    "postgresql" if saved_db.publish => Some(
+                        retry(&format!("publishing to {}", saved_db.host), || {
+                            publish_remote_postgres(local_conn, saved_db, auto_sync_time)
+                        })
+                        .await,
+                    ),
+                    "mysql" => Some(
+                        retry(&format!("syncing with {}", saved_db.host), || {
+                            sync_remote_mysql(
+                                local_conn,
+                                saved_db,
+                                direction,
+                                auto_sync_time,
+                                passphrase.as_deref(),
+                            )
+                        })
+                        .await,
+                    ),
+                    "postgresql" => Some(
+                        retry(&format!("syncing with {}", saved_db.host), || {
+                            sync_remote_postgres(
+                                local_conn,
+                                saved_db,
+                                direction,
+                                auto_sync_time,
+                                passphrase.as_deref(),
+                            )
+                        })
+                        .await,
+                    ),
+                    _ => None,
+                };
+                match e_caused_changes {
+                    Some(Ok(caused_changes)) => {
+                        changes_made = caused_changes || changes_made;
+                        primary_synced = primary_synced || saved_db.is_primary;
+                        idx += 1;
+                        continue;
+                    }
+                    // NOTE: Transient failures that outlasted the retries are only logged -- the
+                    // remote is tried again on the next sync
+                    Some(Err(e)) => {
+                        if !e.is_transient() {
+                            errors.push(e.clone().into());
                         }
+                        saved_dbs.remove(idx);
+                        warn!("error, {e:?}, removing {idx}");
+                        continue;
                     }
-                    "postgresql" => {
-                        let conn_options = PgConnectOptions::new()
-                            .host(&saved_db.host)
-                            .username(&saved_db.user)
-                            .password(&saved_db.password)
-                            .port(saved_db.port)
-                            .database(&saved_db.db)
-                            .ssl_mode(PgSslMode::VerifyFull);
-                        let e_conn: Result<PgPool, String> = run_migrations_and_get_pool(
-                            conn_options,
-                            PG_MIGRATIONS.clone(),
-                            auto_sync_time,
-                        )
-                        .await;
-                        match e_conn {
-                            Ok(conn) => {
-                                let e_caused_changes =
-                                    actually_sync_databases_postgres(&local_conn, &conn).await;
-                                match e_caused_changes {
-                                    Err(e) => {
-                                        errors.push(e);
-                                    }
-                                    Ok(caused_changes) => {
-                                        changes_made = caused_changes || changes_made;
-                                    }
-                                }
+                    None => {}
+                }
+
+                match saved_db.db_type.as_str() {
+                    "git" => {
+                        let e_caused_changes = match git::work_dir(&app, saved_db) {
+                            Ok(work_dir) => {
+                                actually_sync_databases_git(
+                                    &local_conn,
+                                    saved_db,
+                                    work_dir,
+                                    direction,
+                                )
+                                .await
                             }
+                            Err(e) => Err(e),
+                        };
+                        match e_caused_changes {
                             Err(e) => {
-                                errors.push(e.clone());
+                                errors.push(e.clone().into());
                                 saved_dbs.remove(idx);
                                 warn!("error, {e:?}, removing {idx}");
+                                continue;
+                            }
+                            Ok(caused_changes) => {
+                                changes_made = caused_changes || changes_made;
+                                primary_synced = primary_synced || saved_db.is_primary;
                             }
                         }
                     }
                     _ => {
-                        errors.push(format!(
-                            "Unrecognized database type: {:?}",
-                            saved_db.db_type
-                        ));
+                        errors.push(
+                            format!("Unrecognized database type: {:?}", saved_db.db_type).into(),
+                        );
                     }
                 }
 
@@ -205,12 +312,17 @@ async fn sync_databases(db_instances: State<'_, DbInstances>) -> Result<(), Vec<
                 Ok(())
             }
         }
-        _ => Err(vec!["unexpected primary database".to_string()]),
+        _ => Err(vec!["unexpected primary database".to_string().into()]),
     }
 }
 
 #[tauri::command]
-async fn check_database(db_instances: State<'_, DbInstances>, db_id: &str) -> Result<bool, String> {
+async fn check_database(
+    app: AppHandle,
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+) -> Result<bool, ScriptoriumError> {
     let instances = db_instances.0.read().await;
 
     let db = instances
@@ -223,71 +335,436 @@ async fn check_database(db_instances: State<'_, DbInstances>, db_id: &str) -> Re
                 sqlx::query_as("SELECT value FROM settings WHERE key = 'auto_sync_time'")
                     .fetch_all(local_conn)
                     .await
-                    .map_err(|e| e.to_string())
+                    .map_err(ScriptoriumError::from)
                     .and_then(|vs: Vec<ValueString>| {
                         if vs.len() > 0 {
-                            u32::from_str(&vs[0].value).map_err(|e| e.to_string())
+                            u32::from_str(&vs[0].value).map_err(|e| e.to_string().into())
                         } else {
                             Ok(5)
                         }
                     })?;
-            let saved_db: RemoteServer = sqlx::query_as("SELECT id, host, port, db, user, password, db_type FROM remote_servers WHERE id = ?")
+            let mut saved_db: RemoteServer = sqlx::query_as("SELECT id, host, port, db, user, password, password_command, db_type, ssl_mode, ssl_ca, ssl_client_cert, ssl_client_key, ssh_host, ssh_user, ssh_key, proxy, namespace, direction, publish, is_primary, encrypt FROM remote_servers WHERE id = ?")
                 .bind(db_id)
                 .fetch_one(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
-
-            println!("Saved row: {:?}", saved_db);
+                .await?;
+            let credentials_key = encryption.credentials_key();
+            saved_db.password = resolve_password(&saved_db, credentials_key.as_ref()).await?;
+            saved_db.proxy = resolve_proxy(local_conn, &saved_db, credentials_key.as_ref()).await?;
 
             match saved_db.db_type.as_str() {
                 "mysql" => {
-                    let conn_options = MySqlConnectOptions::new()
-                        .host(&saved_db.host)
-                        .username(&saved_db.user)
-                        .password(&saved_db.password)
-                        .port(saved_db.port)
-                        .database(&saved_db.db)
-                        .ssl_mode(MySqlSslMode::Required);
-                    let conn: MySqlPool = run_migrations_and_get_pool(
-                        conn_options,
-                        MYSQL_MIGRATIONS.clone(),
-                        auto_sync_time,
-                    )
-                    .await?;
+                    let (conn, tunnel): (MySqlPool, Option<Tunnel>) =
+                        connect_mysql(&saved_db, auto_sync_time).await?;
+                    conn.close().await;
+                    drop(tunnel);
                     // let conn = MySqlPool::connect_with(conn_options).await.map_err(|e| e.to_string())?;
                     // let migrator = Migrator::new(MIGRATIONS.clone()).await.map_err(|e| e.to_string())?;
                     // migrator.run(&conn).await.map_err(|e| e.to_string())?;
                     Ok(true)
                 }
                 "postgresql" => {
-                    let conn_options = PgConnectOptions::new()
-                        .host(&saved_db.host)
-                        .username(&saved_db.user)
-                        .password(&saved_db.password)
-                        .port(saved_db.port)
-                        .database(&saved_db.db)
-                        .ssl_mode(PgSslMode::VerifyFull);
-                    let conn: PgPool = run_migrations_and_get_pool(
-                        conn_options,
-                        PG_MIGRATIONS.clone(),
-                        auto_sync_time,
-                    )
-                    .await?;
+                    let (conn, tunnel): (PgPool, Option<Tunnel>) =
+                        connect_pg(&saved_db, auto_sync_time).await?;
+                    conn.close().await;
+                    drop(tunnel);
                     // let conn = PgPool::connect_with(conn_options).await.map_err(|e| e.to_string())?;
                     // let migrator = Migrator::new(MIGRATIONS.clone()).await.map_err(|e| e.to_string())?;
                     // migrator.run(&conn).await.map_err(|e| e.to_string())?;
                     Ok(true)
                 }
-                _ => Err(format!(
-                    "Unrecognized database type: {:?}",
-                    saved_db.db_type
-                )),
+                "git" => {
+                    check_git_remote(&saved_db, git::work_dir(&app, &saved_db)?).await?;
+                    Ok(true)
+                }
+                _ => Err(format!("Unrecognized database type: {:?}", saved_db.db_type).into()),
             }
         }
-        _ => Err("unexpected primary database".to_string()),
+        _ => Err("unexpected primary database".to_string().into()),
+    }
+}
+
+async fn local_pool(db_instances: &DbInstances) -> Result<Pool<Sqlite>, ScriptoriumError> {
+    let instances = db_instances.0.read().await;
+
+    match instances.get("sqlite:scriptorium.db") {
+        Some(DbPool::Sqlite(local_conn)) => Ok(local_conn.clone()),
+        Some(_) => Err("unexpected primary database".to_string().into()),
+        None => Err("database not loaded".to_string().into()),
+    }
+}
+
+#[tauri::command]
+async fn start_library_mirror(
+    db_instances: State<'_, DbInstances>,
+    mirror: State<'_, MirrorState>,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let directory: Option<ValueString> = sqlx::query_as("SELECT value FROM settings WHERE key = ?")
+        .bind(MIRROR_DIRECTORY_SETTING)
+        .fetch_optional(&local_conn)
+        .await?;
+    match directory {
+        Some(directory) if !directory.value.is_empty() => {
+            start_mirror(&mirror, local_conn, directory.value.into())
+                .await
+                .map_err(ScriptoriumError::from)
+        }
+        _ => {
+            stop_mirror(&mirror);
+            Ok(())
+        }
+    }
+}
+
+#[tauri::command]
+async fn set_library_mirror(
+    db_instances: State<'_, DbInstances>,
+    mirror: State<'_, MirrorState>,
+    directory: Option<String>,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    match directory.filter(|d| !d.is_empty()) {
+        Some(directory) => {
+            sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = EXCLUDED.value")
+                .bind(MIRROR_DIRECTORY_SETTING)
+                .bind(&directory)
+                .execute(&local_conn)
+                .await?;
+            start_mirror(&mirror, local_conn, directory.into())
+                .await
+                .map_err(ScriptoriumError::from)
+        }
+        None => {
+            sqlx::query("DELETE FROM settings WHERE key = ?")
+                .bind(MIRROR_DIRECTORY_SETTING)
+                .execute(&local_conn)
+                .await?;
+            stop_mirror(&mirror);
+            Ok(())
+        }
     }
 }
 
+#[tauri::command]
+async fn unlock_library(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    passphrase: String,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let credentials = unlock_credentials(&local_conn, &passphrase).await?;
+    encryption.unlock(passphrase, credentials);
+    Ok(())
+}
+
+#[tauri::command]
+fn lock_library(encryption: State<'_, EncryptionState>) {
+    encryption.lock();
+}
+
+#[tauri::command]
+fn is_library_unlocked(encryption: State<'_, EncryptionState>) -> bool {
+    encryption.passphrase().is_some()
+}
+
+#[tauri::command]
+async fn save_remote_server(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    remote: RemoteServerSettings,
+) -> Result<String, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    save_remote_server_settings(&local_conn, encryption.credentials_key().as_ref(), remote)
+        .await
+        .map_err(ScriptoriumError::from)
+}
+
+#[tauri::command]
+async fn save_proxy(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    proxy: &str,
+) -> Result<String, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    save_proxy_setting(&local_conn, encryption.credentials_key().as_ref(), proxy)
+        .await
+        .map_err(ScriptoriumError::from)
+}
+
+#[tauri::command]
+fn parse_remote_url(url: &str) -> Result<RemoteServerSettings, ScriptoriumError> {
+    parse_connection_url(url).map_err(ScriptoriumError::from)
+}
+
+#[tauri::command]
+async fn export_remote_url(
+    db_instances: State<'_, DbInstances>,
+    db_id: &str,
+) -> Result<String, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db: RemoteServer = sqlx::query_as("SELECT id, host, port, db, user, password, password_command, db_type, ssl_mode, ssl_ca, ssl_client_cert, ssl_client_key, ssh_host, ssh_user, ssh_key, proxy, namespace, direction, publish, is_primary, encrypt FROM remote_servers WHERE id = ?")
+        .bind(db_id)
+        .fetch_one(&local_conn)
+        .await?;
+    connection_url(&saved_db).map_err(ScriptoriumError::from)
+}
+
+/// Loads a saved MySQL or PostgreSQL remote, ready to connect to.
+async fn load_database_remote(
+    local_conn: &Pool<Sqlite>,
+    encryption: &EncryptionState,
+    db_id: &str,
+) -> Result<RemoteServer, ScriptoriumError> {
+    let mut saved_db: RemoteServer = sqlx::query_as("SELECT id, host, port, db, user, password, password_command, db_type, ssl_mode, ssl_ca, ssl_client_cert, ssl_client_key, ssh_host, ssh_user, ssh_key, proxy, namespace, direction, publish, is_primary, encrypt FROM remote_servers WHERE id = ?")
+        .bind(db_id)
+        .fetch_one(local_conn)
+        .await?;
+    if saved_db.db_type != "mysql" && saved_db.db_type != "postgresql" {
+        return Err("only MySQL and PostgreSQL remotes support this"
+            .to_string()
+            .into());
+    }
+    let credentials_key = encryption.credentials_key();
+    saved_db.password = resolve_password(&saved_db, credentials_key.as_ref()).await?;
+    saved_db.proxy = resolve_proxy(local_conn, &saved_db, credentials_key.as_ref()).await?;
+    Ok(saved_db)
+}
+
+/// A connection to a MySQL or PostgreSQL remote.
+#[derive(Clone)]
+enum RemoteConn {
+    MySql(MySqlPool),
+    Postgres(PgPool),
+}
+
+impl RemoteConn {
+    async fn close(&self) {
+        match self {
+            RemoteConn::MySql(conn) => conn.close().await,
+            RemoteConn::Postgres(conn) => conn.close().await,
+        }
+    }
+}
+
+/// Connects to a saved MySQL or PostgreSQL remote for `action`, then closes the connection and its
+/// tunnel, whether or not it succeeded.
+async fn with_remote<T, F, Fut>(saved_db: &RemoteServer, action: F) -> Result<T, ScriptoriumError>
+where
+    F: FnOnce(RemoteConn) -> Fut,
+    Fut: Future<Output = Result<T, SyncError>>,
+{
+    let (conn, tunnel) = if saved_db.db_type == "mysql" {
+        let (conn, tunnel) = connect_mysql(saved_db, DEFAULT_AUTO_SYNC_TIME).await?;
+        (RemoteConn::MySql(conn), tunnel)
+    } else {
+        let (conn, tunnel) = connect_pg(saved_db, DEFAULT_AUTO_SYNC_TIME).await?;
+        (RemoteConn::Postgres(conn), tunnel)
+    };
+    let result = action(conn.clone()).await;
+    conn.close().await;
+    drop(tunnel);
+    result.map_err(ScriptoriumError::from)
+}
+
+#[tauri::command]
+async fn share_book(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+    book: &str,
+    grantee: &str,
+    permission: &str,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    check_share(&saved_db.user, book, grantee, permission)?;
+    let (user, prefix) = (&saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => {
+                share_book_mysql(&conn, prefix, user, book, grantee, permission).await
+            }
+            RemoteConn::Postgres(conn) => {
+                share_book_postgres(&conn, user, book, grantee, permission).await
+            }
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn unshare_book(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+    book: &str,
+    grantee: &str,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    let (user, prefix) = (&saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => unshare_book_mysql(&conn, prefix, user, book, grantee).await,
+            RemoteConn::Postgres(conn) => unshare_book_postgres(&conn, user, book, grantee).await,
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn book_grants(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+    book: &str,
+) -> Result<Vec<Grant>, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    let (user, prefix) = (&saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => book_grants_mysql(&conn, prefix, user, book).await,
+            RemoteConn::Postgres(conn) => book_grants_postgres(&conn, user, book).await,
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn remote_compression(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+) -> Result<bool, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    let prefix = saved_db.table_prefix();
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => remote_compression_mysql(&conn, prefix).await,
+            RemoteConn::Postgres(conn) => remote_compression_postgres(&conn).await,
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn set_remote_compression(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+    compress: bool,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    let prefix = saved_db.table_prefix();
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => set_remote_compression_mysql(&conn, prefix, compress).await,
+            RemoteConn::Postgres(conn) => set_remote_compression_postgres(&conn, compress).await,
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn verify_remote(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+) -> Result<Vec<Divergence>, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    // NOTE: Only syncing sets a remote up to be encrypted, so this only uses the passphrase if it
+    // already is
+    let passphrase = encryption.passphrase();
+    let (local_conn, user, prefix) = (&local_conn, &saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => {
+                let key =
+                    remote_key_mysql(&conn, prefix, user, passphrase.as_deref(), false).await?;
+                verify_remote_mysql(local_conn, &conn, prefix, user, key.as_ref()).await
+            }
+            RemoteConn::Postgres(conn) => {
+                let key = remote_key_postgres(&conn, user, passphrase.as_deref(), false).await?;
+                verify_remote_postgres(local_conn, &conn, user, key.as_ref()).await
+            }
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn create_remote_snapshot(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+    name: &str,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    check_snapshot_remote(&saved_db)?;
+    check_snapshot_name(name)?;
+    let (user, prefix) = (&saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => create_snapshot_mysql(&conn, prefix, user, name).await,
+            RemoteConn::Postgres(conn) => create_snapshot_postgres(&conn, user, name).await,
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn remote_snapshots(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+) -> Result<Vec<Snapshot>, ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    check_snapshot_remote(&saved_db)?;
+    let (user, prefix) = (&saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => snapshots_mysql(&conn, prefix, user).await,
+            RemoteConn::Postgres(conn) => snapshots_postgres(&conn, user).await,
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn restore_remote_snapshot(
+    db_instances: State<'_, DbInstances>,
+    encryption: State<'_, EncryptionState>,
+    db_id: &str,
+    name: &str,
+) -> Result<(), ScriptoriumError> {
+    let local_conn = local_pool(&db_instances).await?;
+    let saved_db = load_database_remote(&local_conn, &encryption, db_id).await?;
+    check_snapshot_remote(&saved_db)?;
+    // NOTE: As when verifying, the passphrase is only used if the remote is already encrypted
+    let passphrase = encryption.passphrase();
+    let (local_conn, user, prefix) = (&local_conn, &saved_db.user, saved_db.table_prefix());
+    with_remote(&saved_db, |conn| async move {
+        match conn {
+            RemoteConn::MySql(conn) => {
+                let key =
+                    remote_key_mysql(&conn, prefix, user, passphrase.as_deref(), false).await?;
+                restore_snapshot_mysql(local_conn, &conn, prefix, user, name, key.as_ref()).await
+            }
+            RemoteConn::Postgres(conn) => {
+                let key = remote_key_postgres(&conn, user, passphrase.as_deref(), false).await?;
+                restore_snapshot_postgres(local_conn, &conn, user, name, key.as_ref()).await
+            }
+        }
+    })
+    .await
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     env_logger::init();
@@ -300,9 +777,29 @@ pub fn run() {
                 .build(),
         )
         .plugin(tauri_plugin_opener::init())
+        .manage(MirrorState::default())
+        .manage(EncryptionState::default())
         .invoke_handler(tauri::generate_handler![
             sync_databases,
             check_database,
+            start_library_mirror,
+            set_library_mirror,
+            unlock_library,
+            lock_library,
+            is_library_unlocked,
+            save_remote_server,
+            save_proxy,
+            parse_remote_url,
+            export_remote_url,
+            share_book,
+            unshare_book,
+            book_grants,
+            remote_compression,
+            set_remote_compression,
+            verify_remote,
+            create_remote_snapshot,
+            remote_snapshots,
+            restore_remote_snapshot,
             render_md,
             render_adoc
         ])
@@ -310,23 +807,314 @@ pub fn run() {
         .expect("error while running tauri application");
 }
 
-async fn run_migrations_and_get_pool<DB: Database>(
-    conn_options: <<DB as Database>::Connection as Connection>::Options,
-    migrations: MigrationList,
+/// Syncs with a MySQL remote over a fresh connection, so a retry starts from scratch.
+async fn sync_remote_mysql(
+    local_conn: &Pool<Sqlite>,
+    saved_db: &RemoteServer,
+    direction: Direction,
+    auto_sync_time: u32,
+    passphrase: Option<&str>,
+) -> Result<bool, SyncError> {
+    let mut outbox = read_outbox(local_conn, &saved_db.id).await?;
+    // NOTE: Anything that changed on a secondary is overwritten, not just what changed locally
+    if direction.overwrites() {
+        outbox = outbox.everything();
+    }
+    let (conn, tunnel) = connect_mysql(saved_db, auto_sync_time).await?;
+    let prefix = saved_db.table_prefix();
+    let caused_changes =
+        match remote_key_mysql(&conn, prefix, &saved_db.user, passphrase, saved_db.encrypt).await {
+            Ok(key) => {
+                actually_sync_databases_mysql(
+                    local_conn,
+                    &conn,
+                    prefix,
+                    &saved_db.user,
+                    key.as_ref(),
+                    &outbox,
+                    direction,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+    // NOTE: Close the pool before the tunnel it connects through
+    conn.close().await;
+    drop(tunnel);
+    let caused_changes = caused_changes?;
+    // NOTE: A remote that isn't pushed to has to compare every row once it is again
+    if direction.pushes() && !direction.overwrites() {
+        acknowledge_outbox(local_conn, &saved_db.id, outbox.seq).await?;
+    } else {
+        forget_outbox(local_conn, &saved_db.id).await?;
+    }
+    Ok(caused_changes)
+}
+
+/// Syncs with a PostgreSQL remote over a fresh connection, so a retry starts from scratch.
+async fn sync_remote_postgres(
+    local_conn: &Pool<Sqlite>,
+    saved_db: &RemoteServer,
+    direction: Direction,
+    auto_sync_time: u32,
+    passphrase: Option<&str>,
+) -> Result<bool, SyncError> {
+    let mut outbox = read_outbox(local_conn, &saved_db.id).await?;
+    // NOTE: Anything that changed on a secondary is overwritten, not just what changed locally
+    if direction.overwrites() {
+        outbox = outbox.everything();
+    }
+    let (conn, tunnel) = connect_pg(saved_db, auto_sync_time).await?;
+    let caused_changes =
+        match remote_key_postgres(&conn, &saved_db.user, passphrase, saved_db.encrypt).await {
+            Ok(key) => {
+                actually_sync_databases_postgres(
+                    local_conn,
+                    &conn,
+                    &saved_db.user,
+                    key.as_ref(),
+                    &outbox,
+                    direction,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+    conn.close().await;
+    drop(tunnel);
+    let caused_changes = caused_changes?;
+    // NOTE: A remote that isn't pushed to has to compare every row once it is again
+    if direction.pushes() && !direction.overwrites() {
+        acknowledge_outbox(local_conn, &saved_db.id, outbox.seq).await?;
+    } else {
+        forget_outbox(local_conn, &saved_db.id).await?;
+    }
+    Ok(caused_changes)
+}
+
+/// Publishes to a MySQL remote over a fresh connection, so a retry starts from scratch.
+async fn publish_remote_mysql(
+    local_conn: &Pool<Sqlite>,
+    saved_db: &RemoteServer,
+    auto_sync_time: u32,
+) -> Result<bool, SyncError> {
+    let (conn, tunnel) = connect_mysql(saved_db, auto_sync_time).await?;
+    let published = publish_books_mysql(
+        local_conn,
+        &conn,
+        saved_db.table_prefix(),
+        &saved_db.id,
+        &saved_db.user,
+    )
+    .await;
+    conn.close().await;
+    drop(tunnel);
+    published
+}
+
+/// Publishes to a PostgreSQL remote over a fresh connection, so a retry starts from scratch.
+async fn publish_remote_postgres(
+    local_conn: &Pool<Sqlite>,
+    saved_db: &RemoteServer,
+    auto_sync_time: u32,
+) -> Result<bool, SyncError> {
+    let (conn, tunnel) = connect_pg(saved_db, auto_sync_time).await?;
+    let published = publish_books_postgres(local_conn, &conn, &saved_db.id, &saved_db.user).await;
+    conn.close().await;
+    drop(tunnel);
+    published
+}
+
+async fn connect_mysql(
+    saved_db: &RemoteServer,
     auto_sync_time: u32,
-) -> Result<Pool<DB>, String>
+) -> Result<(MySqlPool, Option<Tunnel>), SyncError> {
+    let tunnel = open_tunnel(saved_db)
+        .await
+        .map_err(|message| ScriptoriumError::Unreachable { message })?;
+    let conn_options = mysql_connect_options(saved_db, tunnel.as_ref())?;
+    let conn = get_pool(conn_options, auto_sync_time).await?;
+    let prefix = saved_db.table_prefix();
+    let migrated = if prefix.is_empty() {
+        run_migrations(&conn, MYSQL_MIGRATIONS.clone()).await
+    } else {
+        run_prefixed_mysql_migrations(&conn, prefix).await
+    };
+    if let Err(e) = migrated {
+        conn.close().await;
+        return Err(e);
+    }
+    if let Err(e) = check_schema_mysql(&conn, prefix).await {
+        conn.close().await;
+        return Err(e);
+    }
+    Ok((conn, tunnel))
+}
+
+async fn connect_pg(
+    saved_db: &RemoteServer,
+    auto_sync_time: u32,
+) -> Result<(PgPool, Option<Tunnel>), SyncError> {
+    let tunnel = open_tunnel(saved_db)
+        .await
+        .map_err(|message| ScriptoriumError::Unreachable { message })?;
+    let conn_options = pg_connect_options(saved_db, tunnel.as_ref())?;
+    let conn = get_pool(conn_options, auto_sync_time).await?;
+    // NOTE: The schema is on the connections' search path, so the migrations and every query after
+    // them use it without naming it
+    let migrated = match &saved_db.namespace {
+        Some(schema) => create_schema_postgres(&conn, schema).await,
+        None => Ok(()),
+    };
+    let migrated = match migrated {
+        Ok(()) => run_migrations(&conn, PG_MIGRATIONS.clone()).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = migrated {
+        conn.close().await;
+        return Err(e);
+    }
+    if let Err(e) = check_schema_postgres(&conn).await {
+        conn.close().await;
+        return Err(e);
+    }
+    Ok((conn, tunnel))
+}
+
+fn newer_remote_error(unknown: Vec<String>) -> Result<(), ScriptoriumError> {
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(ScriptoriumError::SchemaMismatch {
+            message: format!(
+                "this remote was upgraded by a newer version of Scriptorium (migrations {}) -- update Scriptorium to keep syncing with it",
+                unknown.join(", ")
+            ),
+        })
+    }
+}
+
+/// Refuses remotes migrated by a newer build of Scriptorium -- syncing with them would drop the data
+/// in any columns this build doesn't know about. Returns whether any migrations are left to run.
+async fn check_unknown_migrations<DB: Database>(
+    conn: &Pool<DB>,
+    migrator: &Migrator,
+) -> Result<bool, SyncError>
 where
     <DB as Database>::Connection: Migrate,
 {
+    let mut c = conn.acquire().await?;
+    // NOTE: Listed before creating the table, which other users of the remote aren't allowed to
+    let applied = match c.list_applied_migrations().await {
+        Ok(applied) => applied,
+        Err(_) => {
+            c.ensure_migrations_table().await?;
+            c.list_applied_migrations().await?
+        }
+    };
+    newer_remote_error(
+        applied
+            .iter()
+            .filter(|a| !migrator.iter().any(|m| m.version == a.version))
+            .map(|a| a.version.to_string())
+            .collect(),
+    )?;
+    let pending = migrator.iter().any(|m| {
+        !applied
+            .iter()
+            .any(|a| a.version == m.version && a.checksum == m.checksum)
+    });
+    Ok(pending || c.dirty_version().await?.is_some())
+}
+
+/// Runs the MySQL migrations on tables under the remote's prefix. They're tracked in
+/// `<prefix>migrations`, since `_sqlx_migrations` is shared with everything else in the database.
+async fn run_prefixed_mysql_migrations(conn: &MySqlPool, prefix: &str) -> Result<(), SyncError> {
+    let migrations = prefixed_mysql_migrations(prefix);
+    let table = format!("{prefix}migrations");
+    let mut c = conn.acquire().await?;
+    // NOTE: Held until the migrations are done, so two devices can't run the same one at once
+    sqlx::query("SELECT GET_LOCK(CONCAT(DATABASE(), '.', ?), -1)")
+        .bind(&table)
+        .execute(&mut *c)
+        .await?;
+    let migrated = async {
+        sqlx::query(&format!("CREATE TABLE IF NOT EXISTS {table} (version BIGINT PRIMARY KEY, description TEXT NOT NULL, checksum BLOB NOT NULL, installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP)"))
+            .execute(&mut *c)
+            .await?;
+        let applied: Vec<(i64, Vec<u8>)> =
+            sqlx::query_as(&format!("SELECT version, checksum FROM {table}"))
+                .fetch_all(&mut *c)
+                .await?;
+        newer_remote_error(
+            applied
+                .iter()
+                .filter(|(version, _)| !migrations.iter().any(|m| m.version == *version))
+                .map(|(version, _)| version.to_string())
+                .collect(),
+        )?;
+        for migration in &migrations {
+            match applied.iter().find(|(version, _)| *version == migration.version) {
+                Some((_, checksum)) if *checksum != *migration.checksum => {
+                    return Err(ScriptoriumError::SchemaMismatch {
+                        message: format!(
+                            "migration {} was previously applied but has been modified",
+                            migration.version
+                        ),
+                    }
+                    .into());
+                }
+                Some(_) => {}
+                None => {
+                    sqlx::raw_sql(&migration.sql)
+                        .execute(&mut *c)
+                        .await?;
+                    sqlx::query(&format!(
+                        "INSERT INTO {table} (version, description, checksum) VALUES (?, ?, ?)"
+                    ))
+                    .bind(migration.version)
+                    .bind(&*migration.description)
+                    .bind(&*migration.checksum)
+                    .execute(&mut *c)
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    sqlx::query("SELECT RELEASE_LOCK(CONCAT(DATABASE(), '.', ?))")
+        .bind(&table)
+        .execute(&mut *c)
+        .await?;
+    migrated
+}
+
+async fn get_pool<DB: Database>(
+    conn_options: <<DB as Database>::Connection as Connection>::Options,
+    auto_sync_time: u32,
+) -> Result<Pool<DB>, SyncError> {
     let conn = PoolOptions::new()
         .acquire_timeout(Duration::from_secs(auto_sync_time as u64 - 1))
         .connect_with(conn_options)
-        .await
-        .map_err(|e| e.to_string())?;
+        .await?;
     debug!("pool established");
-    let migrator = Migrator::new(migrations).await.map_err(|e| e.to_string())?;
-    debug!("migrator created");
-    migrator.run(&conn).await.map_err(|e| e.to_string())?;
-    debug!("migrations run");
     Ok(conn)
 }
+
+async fn run_migrations<DB: Database>(
+    conn: &Pool<DB>,
+    migrations: MigrationList,
+) -> Result<(), SyncError>
+where
+    <DB as Database>::Connection: Migrate,
+{
+    let migrator = Migrator::new(migrations).await?;
+    debug!("migrator created");
+    // NOTE: Only run when there's something to do, since it takes a lock and creates its table
+    if check_unknown_migrations(conn, &migrator).await? {
+        migrator.run(conn).await?;
+        debug!("migrations run");
+    }
+    Ok(())
+}
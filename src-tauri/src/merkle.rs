@@ -0,0 +1,181 @@
+// Copyright (C) 2025  Athan Clark
+use crate::types::{BucketHash, IdModifiedAndHash};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+// NOTE: Documents are bucketed by the first characters of their ids, and each bucket is summarized by
+// its row count and the sum of its rows' hashes -- a sum doesn't depend on the order rows are read
+// in, so remotes can compute it with a plain `GROUP BY`. Only the buckets that differ are split
+// into longer prefixes, until they're small enough to compare row by row.
+/// Buckets with at most this many rows on either side are compared row by row.
+const LEAF_SIZE: i64 = 256;
+/// Ids are 32 hex characters, so this is far past where buckets get small.
+pub const MAX_PREFIX_LENGTH: usize = 8;
+/// How many hex digits of a row's sha256 are summed -- 60 bits, so sums of millions of rows still
+/// fit in the remotes' integer and decimal types.
+pub const ROW_HASH_DIGITS: usize = 15;
+
+/// The hash summed for each row, over its id and its modification time in whole seconds -- the
+/// precision every backend keeps.
+fn row_hash(document: &IdModifiedAndHash) -> u64 {
+    let digest = Sha256::digest(format!("{}:{}", document.id, document.modified.timestamp()));
+    let hex = format!("{digest:x}");
+    u64::from_str_radix(&hex[..ROW_HASH_DIGITS], 16).unwrap_or_default()
+}
+
+fn bucket_of(id: &str, length: usize) -> String {
+    id.chars().take(length).collect()
+}
+
+/// Summarizes the local documents in the buckets one character longer than `parents`.
+pub fn local_buckets(
+    documents: &HashMap<String, IdModifiedAndHash>,
+    parents: Option<&HashSet<String>>,
+    length: usize,
+) -> HashMap<String, (i64, u128)> {
+    let mut buckets: HashMap<String, (i64, u128)> = HashMap::new();
+    for document in documents.values() {
+        if let Some(parents) = parents {
+            if !parents.contains(&bucket_of(&document.id, length - 1)) {
+                continue;
+            }
+        }
+        let bucket = buckets.entry(bucket_of(&document.id, length)).or_default();
+        bucket.0 += 1;
+        bucket.1 += row_hash(document) as u128;
+    }
+    buckets
+}
+
+/// Parses the buckets a remote summarized.
+pub fn remote_buckets(buckets: Vec<BucketHash>) -> Result<HashMap<String, (i64, u128)>, String> {
+    buckets
+        .into_iter()
+        .map(|b| {
+            let sum = b
+                .sum
+                .parse::<u128>()
+                .map_err(|e| format!("couldn't read the hash of bucket {:?}: {e}", b.bucket))?;
+            Ok((b.bucket, (b.count, sum)))
+        })
+        .collect()
+}
+
+/// Compares one level of buckets, returning the ones that differ but are too large to compare row
+/// by row yet, and adding the rest that differ to `leaves`.
+pub fn differing_buckets(
+    local: &HashMap<String, (i64, u128)>,
+    remote: &HashMap<String, (i64, u128)>,
+    length: usize,
+    leaves: &mut Vec<String>,
+) -> HashSet<String> {
+    let mut split = HashSet::new();
+    for bucket in local.keys().chain(remote.keys()) {
+        let l = local.get(bucket).copied().unwrap_or_default();
+        let r = remote.get(bucket).copied().unwrap_or_default();
+        if l == r || split.contains(bucket) || leaves.contains(bucket) {
+            continue;
+        }
+        // NOTE: A bucket missing from either side transfers whole anyway, and one shorter than the
+        // prefix is a single id
+        if l.0 == 0
+            || r.0 == 0
+            || l.0.max(r.0) <= LEAF_SIZE
+            || bucket.chars().count() < length
+            || length >= MAX_PREFIX_LENGTH
+        {
+            leaves.push(bucket.clone());
+        } else {
+            split.insert(bucket.clone());
+        }
+    }
+    split
+}
+
+/// Whether a document falls in one of the differing buckets.
+pub fn in_buckets(id: &str, leaves: &HashSet<String>) -> bool {
+    (1..=MAX_PREFIX_LENGTH).any(|length| leaves.contains(&bucket_of(id, length)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    /// Documents whose ids all start with `prefix`, so they fall in one top-level bucket.
+    fn documents(prefix: char, count: i64) -> HashMap<String, IdModifiedAndHash> {
+        (0..count)
+            .map(|i| {
+                let hex = format!("{:x}", Sha256::digest(i.to_string()));
+                let id = format!("{prefix}{}", &hex[1..32]);
+                let document = IdModifiedAndHash {
+                    id: id.clone(),
+                    modified: DateTime::from_timestamp(1_700_000_000 + i, 0).unwrap(),
+                    content_hash: None,
+                };
+                (id, document)
+            })
+            .collect()
+    }
+
+    fn touch(documents: &mut HashMap<String, IdModifiedAndHash>, id: &str) {
+        let document = documents.get_mut(id).unwrap();
+        document.modified += chrono::Duration::seconds(1);
+    }
+
+    #[test]
+    fn equal_trees() {
+        let local = documents('a', 100);
+        let remote = local_buckets(&local, None, 1);
+        let mut leaves = vec![];
+        assert!(
+            differing_buckets(&local_buckets(&local, None, 1), &remote, 1, &mut leaves).is_empty()
+        );
+        assert!(leaves.is_empty());
+    }
+
+    #[test]
+    fn one_differing_leaf() {
+        let mut local = documents('a', 100);
+        local.extend(documents('b', 100));
+        let remote = local_buckets(&local, None, 1);
+        let id = local.keys().find(|id| id.starts_with('b')).unwrap().clone();
+        touch(&mut local, &id);
+        let mut leaves = vec![];
+        let split = differing_buckets(&local_buckets(&local, None, 1), &remote, 1, &mut leaves);
+        assert!(split.is_empty());
+        assert_eq!(leaves, ["b"]);
+    }
+
+    #[test]
+    fn descends_past_leaf_size() {
+        let mut local = documents('a', LEAF_SIZE * 2);
+        let remote = local.clone();
+        let id = local.keys().next().unwrap().clone();
+        touch(&mut local, &id);
+
+        let mut leaves = vec![];
+        let split = differing_buckets(
+            &local_buckets(&local, None, 1),
+            &local_buckets(&remote, None, 1),
+            1,
+            &mut leaves,
+        );
+        assert!(leaves.is_empty());
+        assert_eq!(split, HashSet::from(["a".to_string()]));
+
+        let split = differing_buckets(
+            &local_buckets(&local, Some(&split), 2),
+            &local_buckets(&remote, Some(&split), 2),
+            2,
+            &mut leaves,
+        );
+        assert!(split.is_empty());
+        assert_eq!(leaves, [bucket_of(&id, 2)]);
+
+        let leaves = leaves.into_iter().collect();
+        assert!(in_buckets(&id, &leaves));
+        let outside = local.keys().filter(|other| !in_buckets(other, &leaves));
+        assert!(outside.count() > LEAF_SIZE as usize);
+    }
+}
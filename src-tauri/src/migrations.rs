@@ -1,5 +1,7 @@
 // Copyright (C) 2025  Athan Clark
+use crate::schema::prefix_tables;
 use lazy_static::lazy_static;
+use sqlx::migrate::Migration as SqlxMigration;
 use tauri_plugin_sql::{DbInstances, DbPool, Migration, MigrationKind, MigrationList};
 
 lazy_static! {
@@ -166,8 +168,210 @@ BEGIN
 END;
 ",
         },
+        Migration {
+            version: 11,
+            description: "password_command",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN password_command TEXT;
+",
+        },
+        Migration {
+            version: 12,
+            description: "remote_server_tls",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN ssl_mode TEXT;
+ALTER TABLE remote_servers ADD COLUMN ssl_ca TEXT;
+ALTER TABLE remote_servers ADD COLUMN ssl_client_cert TEXT;
+ALTER TABLE remote_servers ADD COLUMN ssl_client_key TEXT;
+",
+        },
+        Migration {
+            version: 13,
+            description: "remote_server_ssh_tunnel",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN ssh_host TEXT;
+ALTER TABLE remote_servers ADD COLUMN ssh_user TEXT;
+ALTER TABLE remote_servers ADD COLUMN ssh_key TEXT;
+",
+        },
+        Migration {
+            version: 14,
+            description: "remote_server_proxy",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN proxy TEXT;
+",
+        },
+        Migration {
+            version: 15,
+            description: "remote_server_namespace",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN namespace TEXT;
+",
+        },
+        Migration {
+            version: 16,
+            description: "document_content_hash",
+            kind: MigrationKind::Up,
+            // NOTE: Only edits to the synced fields count as modifying a document, so filling in its
+            // hash doesn't -- and the hash is cleared by any of them, to be filled in again by sync
+            sql: "
+ALTER TABLE documents ADD COLUMN content_hash TEXT;
+DROP TRIGGER IF EXISTS update_modified_documents;
+CREATE TRIGGER update_modified_documents
+AFTER UPDATE OF book, name, content, syntax, icon, icon_color ON documents
+FOR EACH ROW
+WHEN NEW.modified IS OLD.modified
+BEGIN
+    UPDATE documents
+    SET modified = datetime('now')
+    WHERE id = NEW.id;
+END;
+CREATE TRIGGER clear_content_hash_documents
+AFTER UPDATE OF book, name, content, syntax, icon, icon_color ON documents
+FOR EACH ROW
+BEGIN
+    UPDATE documents
+    SET content_hash = NULL
+    WHERE id = NEW.id;
+END;
+",
+        },
+        Migration {
+            version: 17,
+            description: "change_outbox",
+            kind: MigrationKind::Up,
+            // NOTE: Only edits to the synced fields are logged, so neither filling in a hash nor a
+            // trigger moving `modified` forward counts as a change
+            sql: "
+CREATE TABLE IF NOT EXISTS changes (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    table_name TEXT NOT NULL
+    CHECK (table_name IN ('books', 'documents')),
+    row_id TEXT NOT NULL,
+    operation TEXT NOT NULL
+    CHECK (operation IN ('insert', 'update', 'delete')),
+    changed TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE TABLE IF NOT EXISTS change_acks (
+    remote TEXT PRIMARY KEY,
+    seq INTEGER NOT NULL,
+    FOREIGN KEY (remote)
+        REFERENCES remote_servers(id)
+        ON DELETE CASCADE
+);
+CREATE TRIGGER log_insert_books
+AFTER INSERT ON books
+FOR EACH ROW
+BEGIN
+    INSERT INTO changes (table_name, row_id, operation) VALUES ('books', NEW.id, 'insert');
+END;
+CREATE TRIGGER log_update_books
+AFTER UPDATE OF name, icon, icon_color, trash ON books
+FOR EACH ROW
+BEGIN
+    INSERT INTO changes (table_name, row_id, operation) VALUES ('books', NEW.id, 'update');
+END;
+CREATE TRIGGER log_delete_books
+AFTER DELETE ON books
+FOR EACH ROW
+BEGIN
+    INSERT INTO changes (table_name, row_id, operation) VALUES ('books', OLD.id, 'delete');
+END;
+CREATE TRIGGER log_insert_documents
+AFTER INSERT ON documents
+FOR EACH ROW
+BEGIN
+    INSERT INTO changes (table_name, row_id, operation) VALUES ('documents', NEW.id, 'insert');
+END;
+CREATE TRIGGER log_update_documents
+AFTER UPDATE OF book, name, content, syntax, icon, icon_color ON documents
+FOR EACH ROW
+BEGIN
+    INSERT INTO changes (table_name, row_id, operation) VALUES ('documents', NEW.id, 'update');
+END;
+CREATE TRIGGER log_delete_documents
+AFTER DELETE ON documents
+FOR EACH ROW
+BEGIN
+    INSERT INTO changes (table_name, row_id, operation) VALUES ('documents', OLD.id, 'delete');
+END;
+",
+        },
+        Migration {
+            version: 18,
+            description: "remote_server_direction",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN direction TEXT NOT NULL DEFAULT 'both'
+CHECK (direction IN ('both', 'push', 'pull'));
+",
+        },
+        Migration {
+            version: 19,
+            description: "published_books",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN publish INTEGER NOT NULL DEFAULT 0
+CHECK (publish IN (0, 1));
+CREATE TABLE IF NOT EXISTS published_books (
+    remote TEXT NOT NULL,
+    book TEXT NOT NULL,
+    PRIMARY KEY (remote, book),
+    FOREIGN KEY (remote)
+        REFERENCES remote_servers(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (book)
+        REFERENCES books(id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE
+);
+",
+        },
+        Migration {
+            version: 20,
+            description: "remote_server_primary",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN is_primary INTEGER NOT NULL DEFAULT 0
+CHECK (is_primary IN (0, 1));
+CREATE UNIQUE INDEX IF NOT EXISTS remote_servers_primary ON remote_servers(is_primary)
+WHERE is_primary = 1;
+",
+        },
+        Migration {
+            version: 21,
+            description: "remote_server_encrypt",
+            kind: MigrationKind::Up,
+            // NOTE: Remotes already encrypted stay that way, since they have a key check
+            sql: "
+ALTER TABLE remote_servers ADD COLUMN encrypt INTEGER NOT NULL DEFAULT 0
+CHECK (encrypt IN (0, 1));
+",
+        },
+        Migration {
+            version: 22,
+            description: "remote_server_proxy_password",
+            kind: MigrationKind::Up,
+            // NOTE: Passwords still in proxy URLs are moved here when the library is next unlocked
+            sql: "ALTER TABLE remote_servers ADD COLUMN proxy_password TEXT;",
+        },
     ]);
 
+    // NOTE: There's no version 2 on the remotes -- it created the default book, which only the local
+    // database has. The versions can't be renumbered without changing applied migrations' checksums,
+    // so the two remote lists differ at 6 and 7, and after 13:
+    //
+    // | Version | MySQL        | PostgreSQL           |
+    // |---------|--------------|----------------------|
+    // | 6       | key_check    | utc_timestamp        |
+    // | 7       | utc_datetime | key_check            |
+    // | 14      |              | scope_deleted_shared |
+    // | 15      |              | user_role            |
     pub static ref MYSQL_MIGRATIONS: MigrationList = MigrationList(vec![
         Migration {
             version: 1,
@@ -221,6 +425,162 @@ CHECK (trash IN (0, 1));
 CREATE TABLE IF NOT EXISTS deleted (
     id VARCHAR(32) PRIMARY KEY
 );
+",
+        },
+        Migration {
+            version: 6,
+            description: "key_check",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS key_check (
+    id INTEGER PRIMARY KEY,
+    salt TEXT NOT NULL,
+    check_value TEXT NOT NULL
+);
+",
+        },
+        Migration {
+            version: 7,
+            description: "utc_datetime",
+            kind: MigrationKind::Up,
+            // NOTE: `TIMESTAMP` values are converted to and from the session's time zone, so they're
+            // converted at UTC here, and kept as `DATETIME` -- which is never converted -- after.
+            sql: "
+SET time_zone = '+00:00';
+ALTER TABLE books MODIFY modified DATETIME NOT NULL;
+ALTER TABLE documents MODIFY modified DATETIME NOT NULL;
+",
+        },
+        Migration {
+            version: 8,
+            description: "owners",
+            kind: MigrationKind::Up,
+            // NOTE: Rows from before there were owners belong to whoever migrates the remote. The
+            // foreign key to `books` wasn't named, so its name is looked up.
+            sql: "
+CREATE TABLE IF NOT EXISTS users (
+    name VARCHAR(255) PRIMARY KEY,
+    created DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT IGNORE INTO users (name) VALUES (SUBSTRING_INDEX(USER(), '@', 1));
+ALTER TABLE books ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE documents ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE deleted ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE key_check ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT '';
+UPDATE books SET owner = SUBSTRING_INDEX(USER(), '@', 1);
+UPDATE documents SET owner = SUBSTRING_INDEX(USER(), '@', 1);
+UPDATE deleted SET owner = SUBSTRING_INDEX(USER(), '@', 1);
+UPDATE key_check SET owner = SUBSTRING_INDEX(USER(), '@', 1);
+SET @documents_book_key = (
+    SELECT CONSTRAINT_NAME FROM information_schema.KEY_COLUMN_USAGE
+    WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'documents' AND COLUMN_NAME = 'book'
+    AND REFERENCED_TABLE_NAME = 'books'
+    LIMIT 1
+);
+SET @drop_documents_book_key = CONCAT(
+    'ALTER TABLE `', 'documents', '` DROP FOREIGN KEY `', @documents_book_key, '`'
+);
+PREPARE drop_documents_book_key FROM @drop_documents_book_key;
+EXECUTE drop_documents_book_key;
+DEALLOCATE PREPARE drop_documents_book_key;
+ALTER TABLE books DROP PRIMARY KEY, ADD PRIMARY KEY (owner, id);
+ALTER TABLE documents DROP PRIMARY KEY, ADD PRIMARY KEY (owner, id);
+ALTER TABLE documents ADD FOREIGN KEY (owner, book)
+    REFERENCES books(owner, id)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE;
+ALTER TABLE deleted DROP PRIMARY KEY, ADD PRIMARY KEY (owner, id);
+ALTER TABLE key_check DROP PRIMARY KEY, ADD PRIMARY KEY (owner, id);
+",
+        },
+        Migration {
+            version: 9,
+            description: "book_shares",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS book_shares (
+    owner VARCHAR(255) NOT NULL,
+    book VARCHAR(32) NOT NULL,
+    grantee VARCHAR(255) NOT NULL,
+    permission VARCHAR(5) NOT NULL
+    CHECK (permission IN ('read', 'write')),
+    PRIMARY KEY (owner, book, grantee),
+    FOREIGN KEY (owner, book)
+        REFERENCES books(owner, id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE
+);
+",
+        },
+        Migration {
+            version: 10,
+            description: "document_content_hash",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE documents ADD COLUMN content_hash VARCHAR(64);
+",
+        },
+        Migration {
+            version: 11,
+            description: "remote_metadata",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS metadata (
+    name VARCHAR(64) PRIMARY KEY,
+    value TEXT NOT NULL
+);
+",
+        },
+        Migration {
+            version: 12,
+            description: "published_books",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE books ADD COLUMN published BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE documents ADD COLUMN rendered MEDIUMTEXT;
+",
+        },
+        Migration {
+            version: 13,
+            description: "snapshots",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS snapshots (
+    owner VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    created DATETIME NOT NULL,
+    PRIMARY KEY (owner, name)
+);
+CREATE TABLE IF NOT EXISTS snapshot_books (
+    owner VARCHAR(255) NOT NULL,
+    snapshot VARCHAR(255) NOT NULL,
+    id VARCHAR(32) NOT NULL,
+    name TEXT,
+    modified DATETIME NOT NULL,
+    icon TEXT,
+    icon_color TEXT,
+    trash INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (owner, snapshot, id),
+    FOREIGN KEY (owner, snapshot)
+        REFERENCES snapshots(owner, name)
+        ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS snapshot_documents (
+    owner VARCHAR(255) NOT NULL,
+    snapshot VARCHAR(255) NOT NULL,
+    id VARCHAR(32) NOT NULL,
+    book VARCHAR(32) NOT NULL,
+    name TEXT,
+    content TEXT,
+    syntax TEXT NOT NULL,
+    modified DATETIME NOT NULL,
+    icon TEXT,
+    icon_color TEXT,
+    PRIMARY KEY (owner, snapshot, id),
+    FOREIGN KEY (owner, snapshot)
+        REFERENCES snapshots(owner, name)
+        ON DELETE CASCADE
+);
 ",
         },
     ]);
@@ -287,7 +647,262 @@ CREATE TABLE IF NOT EXISTS deleted (
             sql: "
 ALTER TABLE books ALTER COLUMN modified TYPE TIMESTAMPTZ;
 ALTER TABLE documents ALTER COLUMN modified TYPE TIMESTAMPTZ;
+",
+        },
+        Migration {
+            version: 7,
+            description: "key_check",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS key_check (
+    id INTEGER PRIMARY KEY,
+    salt TEXT NOT NULL,
+    check_value TEXT NOT NULL
+);
+",
+        },
+        Migration {
+            version: 8,
+            description: "owners",
+            kind: MigrationKind::Up,
+            // NOTE: Rows from before there were owners belong to whoever migrates the remote
+            sql: "
+CREATE TABLE IF NOT EXISTS users (
+    name VARCHAR(255) PRIMARY KEY,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO users (name) VALUES (current_user) ON CONFLICT DO NOTHING;
+ALTER TABLE books ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT current_user;
+ALTER TABLE documents ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT current_user;
+ALTER TABLE deleted ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT current_user;
+ALTER TABLE key_check ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT current_user;
+ALTER TABLE documents DROP CONSTRAINT documents_book_fkey;
+ALTER TABLE books DROP CONSTRAINT books_pkey, ADD PRIMARY KEY (owner, id);
+ALTER TABLE documents DROP CONSTRAINT documents_pkey, ADD PRIMARY KEY (owner, id);
+ALTER TABLE documents ADD FOREIGN KEY (owner, book)
+    REFERENCES books(owner, id)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE;
+ALTER TABLE deleted DROP CONSTRAINT deleted_pkey, ADD PRIMARY KEY (owner, id);
+ALTER TABLE key_check DROP CONSTRAINT key_check_pkey, ADD PRIMARY KEY (owner, id);
+
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+ALTER TABLE users FORCE ROW LEVEL SECURITY;
+CREATE POLICY users_select ON users FOR SELECT USING (true);
+CREATE POLICY users_insert ON users FOR INSERT WITH CHECK (name = current_user);
+ALTER TABLE books ENABLE ROW LEVEL SECURITY;
+ALTER TABLE books FORCE ROW LEVEL SECURITY;
+CREATE POLICY books_owner ON books USING (owner = current_user) WITH CHECK (owner = current_user);
+ALTER TABLE documents ENABLE ROW LEVEL SECURITY;
+ALTER TABLE documents FORCE ROW LEVEL SECURITY;
+CREATE POLICY documents_owner ON documents USING (owner = current_user) WITH CHECK (owner = current_user);
+ALTER TABLE deleted ENABLE ROW LEVEL SECURITY;
+ALTER TABLE deleted FORCE ROW LEVEL SECURITY;
+CREATE POLICY deleted_owner ON deleted USING (owner = current_user) WITH CHECK (owner = current_user);
+ALTER TABLE key_check ENABLE ROW LEVEL SECURITY;
+ALTER TABLE key_check FORCE ROW LEVEL SECURITY;
+CREATE POLICY key_check_owner ON key_check USING (owner = current_user) WITH CHECK (owner = current_user);
+",
+        },
+        Migration {
+            version: 9,
+            description: "book_shares",
+            kind: MigrationKind::Up,
+            // NOTE: Grantees can read the books shared with them, and their owner's deletions. With
+            // write permission, they can change the book and its documents, and delete documents.
+            sql: "
+CREATE TABLE IF NOT EXISTS book_shares (
+    owner VARCHAR(255) NOT NULL,
+    book VARCHAR(32) NOT NULL,
+    grantee VARCHAR(255) NOT NULL,
+    permission VARCHAR(5) NOT NULL
+    CHECK (permission IN ('read', 'write')),
+    PRIMARY KEY (owner, book, grantee),
+    FOREIGN KEY (owner, book)
+        REFERENCES books(owner, id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE
+);
+ALTER TABLE book_shares ENABLE ROW LEVEL SECURITY;
+ALTER TABLE book_shares FORCE ROW LEVEL SECURITY;
+CREATE POLICY book_shares_owner ON book_shares USING (owner = current_user) WITH CHECK (owner = current_user);
+CREATE POLICY book_shares_grantee ON book_shares FOR SELECT USING (grantee = current_user);
+CREATE POLICY books_shared ON books FOR SELECT USING (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = books.owner AND s.book = books.id AND s.grantee = current_user)
+);
+CREATE POLICY books_shared_write ON books FOR UPDATE USING (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = books.owner AND s.book = books.id AND s.grantee = current_user AND s.permission = 'write')
+);
+CREATE POLICY documents_shared ON documents FOR SELECT USING (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = documents.owner AND s.book = documents.book AND s.grantee = current_user)
+);
+CREATE POLICY documents_shared_write ON documents USING (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = documents.owner AND s.book = documents.book AND s.grantee = current_user AND s.permission = 'write')
+) WITH CHECK (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = documents.owner AND s.book = documents.book AND s.grantee = current_user AND s.permission = 'write')
+);
+CREATE POLICY deleted_shared ON deleted FOR SELECT USING (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = deleted.owner AND s.grantee = current_user)
+);
+CREATE POLICY deleted_shared_write ON deleted FOR INSERT WITH CHECK (
+    EXISTS (SELECT 1 FROM documents d JOIN book_shares s ON s.owner = d.owner AND s.book = d.book WHERE d.owner = deleted.owner AND d.id = deleted.id AND s.grantee = current_user AND s.permission = 'write')
+);
+",
+        },
+        Migration {
+            version: 10,
+            description: "document_content_hash",
+            kind: MigrationKind::Up,
+            sql: "
+ALTER TABLE documents ADD COLUMN content_hash VARCHAR(64);
+",
+        },
+        Migration {
+            version: 11,
+            description: "remote_metadata",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS metadata (
+    name VARCHAR(64) PRIMARY KEY,
+    value TEXT NOT NULL
+);
+",
+        },
+        Migration {
+            version: 12,
+            description: "published_books",
+            kind: MigrationKind::Up,
+            // NOTE: Published books and their documents can be read by every user of the remote
+            sql: "
+ALTER TABLE books ADD COLUMN published BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE documents ADD COLUMN rendered TEXT;
+CREATE POLICY books_published ON books FOR SELECT USING (published);
+CREATE POLICY documents_published ON documents FOR SELECT USING (
+    EXISTS (SELECT 1 FROM books b WHERE b.owner = documents.owner AND b.id = documents.book AND b.published)
+);
+",
+        },
+        Migration {
+            version: 13,
+            description: "snapshots",
+            kind: MigrationKind::Up,
+            sql: "
+CREATE TABLE IF NOT EXISTS snapshots (
+    owner VARCHAR(255) NOT NULL DEFAULT current_user,
+    name VARCHAR(255) NOT NULL,
+    created TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (owner, name)
+);
+CREATE TABLE IF NOT EXISTS snapshot_books (
+    owner VARCHAR(255) NOT NULL DEFAULT current_user,
+    snapshot VARCHAR(255) NOT NULL,
+    id VARCHAR(32) NOT NULL,
+    name TEXT,
+    modified TIMESTAMPTZ NOT NULL,
+    icon TEXT,
+    icon_color TEXT,
+    trash INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (owner, snapshot, id),
+    FOREIGN KEY (owner, snapshot)
+        REFERENCES snapshots(owner, name)
+        ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS snapshot_documents (
+    owner VARCHAR(255) NOT NULL DEFAULT current_user,
+    snapshot VARCHAR(255) NOT NULL,
+    id VARCHAR(32) NOT NULL,
+    book VARCHAR(32) NOT NULL,
+    name TEXT,
+    content TEXT,
+    syntax TEXT NOT NULL,
+    modified TIMESTAMPTZ NOT NULL,
+    icon TEXT,
+    icon_color TEXT,
+    PRIMARY KEY (owner, snapshot, id),
+    FOREIGN KEY (owner, snapshot)
+        REFERENCES snapshots(owner, name)
+        ON DELETE CASCADE
+);
+ALTER TABLE snapshots ENABLE ROW LEVEL SECURITY;
+ALTER TABLE snapshots FORCE ROW LEVEL SECURITY;
+CREATE POLICY snapshots_owner ON snapshots USING (owner = current_user) WITH CHECK (owner = current_user);
+ALTER TABLE snapshot_books ENABLE ROW LEVEL SECURITY;
+ALTER TABLE snapshot_books FORCE ROW LEVEL SECURITY;
+CREATE POLICY snapshot_books_owner ON snapshot_books USING (owner = current_user) WITH CHECK (owner = current_user);
+ALTER TABLE snapshot_documents ENABLE ROW LEVEL SECURITY;
+ALTER TABLE snapshot_documents FORCE ROW LEVEL SECURITY;
+CREATE POLICY snapshot_documents_owner ON snapshot_documents USING (owner = current_user) WITH CHECK (owner = current_user);
+",
+        },
+        Migration {
+            version: 14,
+            description: "scope_deleted_shared",
+            kind: MigrationKind::Up,
+            // NOTE: Grantees could read every one of the owner's deletions. A tombstone outlives its
+            // document, so its book is recorded when it's added, and only those of shared books
+            // (or the shared book itself) can be read.
+            sql: "
+ALTER TABLE deleted ADD COLUMN book VARCHAR(32);
+CREATE OR REPLACE FUNCTION deleted_book() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.book IS NULL THEN
+        SELECT book INTO NEW.book FROM documents WHERE owner = NEW.owner AND id = NEW.id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER deleted_book BEFORE INSERT ON deleted FOR EACH ROW EXECUTE FUNCTION deleted_book();
+UPDATE deleted SET book = d.book FROM documents d WHERE d.owner = deleted.owner AND d.id = deleted.id;
+DROP POLICY deleted_shared ON deleted;
+CREATE POLICY deleted_shared ON deleted FOR SELECT USING (
+    EXISTS (SELECT 1 FROM book_shares s WHERE s.owner = deleted.owner AND (s.book = deleted.id OR s.book = deleted.book) AND s.grantee = current_user)
+);
+",
+        },
+        Migration {
+            version: 15,
+            description: "user_role",
+            kind: MigrationKind::Up,
+            // NOTE: Every other user is let in with `GRANT scriptorium_user TO <user>`, and row-level
+            // security keeps them to their own rows. Only the user who migrates the remote can change
+            // its metadata, or run later migrations. A user who can't create roles leaves it out.
+            sql: "
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'scriptorium_user') THEN
+        BEGIN
+            CREATE ROLE scriptorium_user NOLOGIN;
+        EXCEPTION WHEN insufficient_privilege THEN
+            RAISE NOTICE 'scriptorium_user can''t be created, so other users need their own grants';
+            RETURN;
+        END;
+    END IF;
+    EXECUTE format('GRANT USAGE ON SCHEMA %I TO scriptorium_user', current_schema());
+    GRANT SELECT ON _sqlx_migrations, metadata TO scriptorium_user;
+    GRANT SELECT, INSERT ON users TO scriptorium_user;
+    GRANT SELECT, INSERT, UPDATE, DELETE
+        ON books, documents, deleted, key_check, book_shares, snapshots, snapshot_books, snapshot_documents
+        TO scriptorium_user;
+END
+$$;
 ",
         },
     ]);
 }
+
+/// The MySQL migrations, with the remote's table prefix put in front of every table they touch.
+pub fn prefixed_mysql_migrations(prefix: &str) -> Vec<SqlxMigration> {
+    MYSQL_MIGRATIONS
+        .0
+        .iter()
+        .map(|migration| {
+            SqlxMigration::new(
+                migration.version,
+                migration.description.into(),
+                migration.kind.clone().into(),
+                prefix_tables(prefix, migration.sql).into(),
+                false,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,559 @@
+// Copyright (C) 2025  Athan Clark
+use crate::files::{
+    document_path, extension_syntax, file_modified, is_valid_id, parse_book, read_document,
+    read_library, read_local_library, write_library, Layout, BOOK_FILE,
+};
+use crate::types::{Book, Document};
+use log::{debug, warn};
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+use sqlx::{Pool, Sqlite};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+pub const MIRROR_DIRECTORY_SETTING: &str = "mirror_directory";
+// NOTE: How often the database is checked for edits made in the app
+const EXPORT_INTERVAL: Duration = Duration::from_secs(2);
+const DEBOUNCE_TIME: Duration = Duration::from_millis(500);
+// NOTE: These books always exist, so removing their directories doesn't delete them
+const PERMANENT_BOOKS: [&str; 2] = ["default", "trash"];
+
+/// Holds the file watcher for the mirror directory, if there is one. Dropping the watcher also
+/// stops the thread that services it.
+#[derive(Default)]
+pub struct MirrorState(Mutex<Option<Debouncer<RecommendedWatcher>>>);
+
+/// What the last export wrote out.
+#[derive(Default)]
+struct Exported {
+    /// The last change logged in the database, to tell whether it changed since
+    fingerprint: Option<i64>,
+    /// The files as they were last seen, and when each was modified -- ones that differ were edited
+    /// since, and may not have been imported yet
+    files: HashMap<PathBuf, SystemTime>,
+}
+
+impl Exported {
+    /// Whether `path` changed since it was last seen -- the watcher also reports files that were
+    /// only read, or written by the export itself.
+    fn changed(&self, path: &Path) -> bool {
+        match (self.files.get(path), modified(path)) {
+            (Some(seen), Some(modified)) => *seen != modified,
+            _ => true,
+        }
+    }
+
+    fn see(&mut self, path: &Path) {
+        match modified(path) {
+            Some(modified) if path.is_file() => self.files.insert(path.to_path_buf(), modified),
+            _ => self.files.remove(path),
+        };
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub fn stop_mirror(state: &MirrorState) {
+    if state.0.lock().unwrap().take().is_some() {
+        debug!("mirror stopped");
+    }
+}
+
+/// Reconciles the directory with the database, then keeps them in sync until `stop_mirror` is
+/// called: edits to files are imported, and edits made in the app are written out.
+pub async fn start_mirror(
+    state: &MirrorState,
+    local_conn: Pool<Sqlite>,
+    root: PathBuf,
+) -> Result<(), String> {
+    stop_mirror(state);
+    fs::create_dir_all(&root).map_err(|e| e.to_string())?;
+    let exported = reconcile(&local_conn, &root).await?;
+
+    let (tx, rx) = channel();
+    let mut debouncer = new_debouncer(DEBOUNCE_TIME, tx).map_err(|e| e.to_string())?;
+    debouncer
+        .watcher()
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| e.to_string())?;
+    std::thread::spawn(move || watch(local_conn, root, rx, exported));
+    *state.0.lock().unwrap() = Some(debouncer);
+    debug!("mirror started");
+    Ok(())
+}
+
+fn watch(
+    local_conn: Pool<Sqlite>,
+    root: PathBuf,
+    rx: Receiver<DebounceEventResult>,
+    mut exported: Exported,
+) {
+    loop {
+        match rx.recv_timeout(EXPORT_INTERVAL) {
+            Ok(Ok(events)) => {
+                let paths: BTreeSet<PathBuf> = events.into_iter().map(|e| e.path).collect();
+                for path in paths {
+                    if !exported.changed(&path) {
+                        continue;
+                    }
+                    let imported =
+                        tauri::async_runtime::block_on(import_path(&local_conn, &root, &path));
+                    match imported {
+                        Ok(true) => debug!("imported {}", path.display()),
+                        Ok(false) => {}
+                        Err(e) => warn!("couldn't import {}: {e}", path.display()),
+                    }
+                    exported.see(&path);
+                }
+            }
+            Ok(Err(e)) => warn!("mirror watcher error: {e:?}"),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let result = tauri::async_runtime::block_on(export(&local_conn, &root, &mut exported));
+        if let Err(e) = result {
+            warn!("couldn't export library to {}: {e}", root.display());
+        }
+    }
+}
+
+/// Writes the library out, but only when the database changed since the last export. Files edited
+/// since then are imported first, so they aren't overwritten before the watcher reports them.
+async fn export(
+    local_conn: &Pool<Sqlite>,
+    root: &Path,
+    exported: &mut Exported,
+) -> Result<(), String> {
+    if exported.fingerprint.is_some() {
+        import_edited(local_conn, root, &mut exported.files).await?;
+    }
+    // NOTE: Every edit is logged in the outbox, and its sequence only ever grows -- even once the
+    // changes every remote acknowledged are dropped
+    let current: Option<i64> =
+        sqlx::query_scalar("SELECT seq FROM sqlite_sequence WHERE name = 'changes'")
+            .fetch_optional(local_conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    let current = current.unwrap_or_default();
+    if exported.fingerprint != Some(current) {
+        let library = read_local_library(local_conn).await?;
+        write_library(root, &library, Layout::Mirror, true)?;
+        exported.fingerprint = Some(current);
+        exported.files = mirrored_files(root)?;
+        debug!("library exported to {}", root.display());
+    }
+    Ok(())
+}
+
+/// The files in the book directories, and when each was last modified.
+fn mirrored_files(root: &Path) -> Result<HashMap<PathBuf, SystemTime>, String> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(root).map_err(|e| e.to_string())? {
+        let dir = entry.map_err(|e| e.to_string())?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+            let path = file.map_err(|e| e.to_string())?.path();
+            if let Some(modified) = modified(&path) {
+                files.insert(path, modified);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Imports the files added, edited or removed since they were last seen, as `files`. Each is only
+/// tried once, like the watcher does.
+async fn import_edited(
+    local_conn: &Pool<Sqlite>,
+    root: &Path,
+    files: &mut HashMap<PathBuf, SystemTime>,
+) -> Result<(), String> {
+    let current = mirrored_files(root)?;
+    let mut paths = BTreeSet::new();
+    for (path, modified) in &current {
+        if files.get(path) != Some(modified) {
+            paths.insert(path.clone());
+        }
+    }
+    for path in files.keys().filter(|path| !current.contains_key(*path)) {
+        match path.parent() {
+            // NOTE: The whole book was removed
+            Some(dir) if path.ends_with(BOOK_FILE) && !dir.exists() => {
+                paths.insert(dir.to_path_buf());
+            }
+            _ => {
+                paths.insert(path.clone());
+            }
+        }
+    }
+    for path in paths {
+        match import_path(local_conn, root, &path).await {
+            Ok(true) => debug!("imported {} before exporting", path.display()),
+            Ok(false) => {}
+            Err(e) => warn!("couldn't import {}: {e}", path.display()),
+        }
+    }
+    *files = current;
+    Ok(())
+}
+
+/// Imports anything edited in the directory while the mirror wasn't running, when the file is
+/// newer than the database's copy, then writes out the rest of the library.
+async fn reconcile(local_conn: &Pool<Sqlite>, root: &Path) -> Result<Exported, String> {
+    let on_disk = read_library(root)?;
+    let local = read_local_library(local_conn).await?;
+    let local_books: HashMap<&String, &Book> = local.books.iter().map(|b| (&b.id, b)).collect();
+    let local_documents: HashMap<&String, &Document> =
+        local.documents.iter().map(|d| (&d.id, d)).collect();
+
+    for book in &on_disk.books {
+        let newer = match local_books.get(&book.id) {
+            None => !local.deleted.contains(&book.id),
+            Some(row) => !same_book(row, book) && book.modified > row.modified,
+        };
+        if newer {
+            upsert_book(local_conn, book).await?;
+        }
+    }
+    for document in &on_disk.documents {
+        let newer = match local_documents.get(&document.id) {
+            None => !local.deleted.contains(&document.id),
+            Some(row) => !same_document(row, document) && document.modified > row.modified,
+        };
+        if newer {
+            upsert_document(local_conn, document).await?;
+        }
+    }
+
+    let mut exported = Exported::default();
+    export(local_conn, root, &mut exported).await?;
+    Ok(exported)
+}
+
+/// Imports a single changed path from the mirror directory. Returns whether the database changed.
+async fn import_path(local_conn: &Pool<Sqlite>, root: &Path, path: &Path) -> Result<bool, String> {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Ok(false);
+    };
+    let components: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    match components.as_slice() {
+        [book, ..] if !is_valid_id(book) => Ok(false),
+        [book] => {
+            if path.exists() || PERMANENT_BOOKS.contains(&book.as_str()) {
+                return Ok(false);
+            }
+            // NOTE: Deleting rows populates `deleted` through triggers
+            let removed = sqlx::query("DELETE FROM documents WHERE book = ?")
+                .bind(book)
+                .execute(local_conn)
+                .await
+                .map_err(|e| e.to_string())?
+                .rows_affected()
+                + sqlx::query("DELETE FROM books WHERE id = ?")
+                    .bind(book)
+                    .execute(local_conn)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .rows_affected();
+            Ok(removed > 0)
+        }
+        [book, file] if file == BOOK_FILE => {
+            if !path.is_file() {
+                return Ok(false);
+            }
+            let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let on_disk = parse_book(book, &text, file_modified(path))?;
+            let row: Option<Book> = sqlx::query_as(
+                "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE id = ?",
+            )
+            .bind(book)
+            .fetch_optional(local_conn)
+            .await
+            .map_err(|e| e.to_string())?;
+            if row.is_some_and(|row| same_book(&row, &on_disk)) {
+                return Ok(false);
+            }
+            upsert_book(local_conn, &on_disk).await?;
+            Ok(true)
+        }
+        [book, file] if !file.starts_with('.') => {
+            let (Some(id), Some(extension)) = (path.file_stem(), path.extension()) else {
+                return Ok(false);
+            };
+            if !is_valid_id(&id.to_string_lossy())
+                || extension_syntax(&extension.to_string_lossy()).is_none()
+            {
+                return Ok(false);
+            }
+            let row: Option<Document> = sqlx::query_as(
+                "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE id = ?",
+            )
+            .bind(id.to_string_lossy())
+            .fetch_optional(local_conn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            if !path.is_file() {
+                // NOTE: Only delete the document when this was actually its file -- a different
+                // file name means it was moved or its syntax changed
+                return match row {
+                    Some(row) if document_path(root, &row) == path => {
+                        sqlx::query("DELETE FROM documents WHERE id = ?")
+                            .bind(&row.id)
+                            .execute(local_conn)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                };
+            }
+
+            let on_disk = read_document(path, book)?;
+            if row.is_some_and(|row| same_document(&row, &on_disk)) {
+                return Ok(false);
+            }
+            sqlx::query("INSERT OR IGNORE INTO books (id, name) VALUES (?, ?)")
+                .bind(book)
+                .bind(book)
+                .execute(local_conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            upsert_document(local_conn, &on_disk).await?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn same_book(a: &Book, b: &Book) -> bool {
+    a.name == b.name && a.icon == b.icon && a.icon_color == b.icon_color && a.trash == b.trash
+}
+
+fn same_document(a: &Document, b: &Document) -> bool {
+    a.book == b.book
+        && a.name == b.name
+        && a.content == b.content
+        && a.syntax == b.syntax
+        && a.icon == b.icon
+        && a.icon_color == b.icon_color
+}
+
+// NOTE: `modified` is left out of these upserts, so the database stamps the edit itself
+
+async fn upsert_book(local_conn: &Pool<Sqlite>, book: &Book) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO books (id, name, icon, icon_color, trash) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, icon = EXCLUDED.icon, icon_color = EXCLUDED.icon_color, trash = EXCLUDED.trash",
+    )
+    .bind(&book.id)
+    .bind(&book.name)
+    .bind(&book.icon)
+    .bind(&book.icon_color)
+    .bind(book.trash)
+    .execute(local_conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn upsert_document(local_conn: &Pool<Sqlite>, document: &Document) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO documents (id, book, name, content, syntax, icon, icon_color) VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (id) DO UPDATE SET book = EXCLUDED.book, name = EXCLUDED.name, content = EXCLUDED.content, syntax = EXCLUDED.syntax, icon = EXCLUDED.icon, icon_color = EXCLUDED.icon_color",
+    )
+    .bind(&document.id)
+    .bind(&document.book)
+    .bind(&document.name)
+    .bind(&document.content)
+    .bind(&document.syntax)
+    .bind(&document.icon)
+    .bind(&document.icon_color)
+    .execute(local_conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::SQLITE_MIGRATIONS;
+    use sqlx::{migrate::Migrator, sqlite::SqlitePoolOptions};
+
+    const DOCUMENT: &str = "0000000000000000000000000000000d";
+    const BOOK: &str = "0000000000000000000000000000000b";
+
+    /// Runs `test` against an empty library and mirror directory.
+    fn with_mirror<F>(name: &str, test: impl FnOnce(Pool<Sqlite>, PathBuf) -> F)
+    where
+        F: std::future::Future<Output = ()>,
+    {
+        let root = std::env::temp_dir().join(format!("scriptorium-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("default")).unwrap();
+        tauri::async_runtime::block_on(async {
+            let local_conn = SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            Migrator::new(SQLITE_MIGRATIONS.clone())
+                .await
+                .unwrap()
+                .run(&local_conn)
+                .await
+                .unwrap();
+            test(local_conn, root.clone()).await;
+        });
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    async fn content(local_conn: &Pool<Sqlite>, id: &str) -> Option<String> {
+        sqlx::query_scalar("SELECT content FROM documents WHERE id = ?")
+            .bind(id)
+            .fetch_optional(local_conn)
+            .await
+            .unwrap()
+    }
+
+    async fn is_deleted(local_conn: &Pool<Sqlite>, id: &str) -> bool {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM deleted WHERE id = ?")
+            .bind(id)
+            .fetch_one(local_conn)
+            .await
+            .unwrap();
+        count > 0
+    }
+
+    #[test]
+    fn document_files() {
+        with_mirror("mirror-documents", |local_conn, root| async move {
+            let path = root.join("default").join(format!("{DOCUMENT}.md"));
+            fs::write(&path, "# Hello").unwrap();
+            assert!(import_path(&local_conn, &root, &path).await.unwrap());
+            assert_eq!(
+                content(&local_conn, DOCUMENT).await.as_deref(),
+                Some("# Hello")
+            );
+            assert!(!import_path(&local_conn, &root, &path).await.unwrap());
+
+            fs::write(&path, "# Edited").unwrap();
+            assert!(import_path(&local_conn, &root, &path).await.unwrap());
+            assert_eq!(
+                content(&local_conn, DOCUMENT).await.as_deref(),
+                Some("# Edited")
+            );
+
+            // NOTE: Another file of the same id isn't the document's
+            let other = root.join("default").join(format!("{DOCUMENT}.adoc"));
+            assert!(!import_path(&local_conn, &root, &other).await.unwrap());
+            fs::remove_file(&path).unwrap();
+            assert!(import_path(&local_conn, &root, &path).await.unwrap());
+            assert_eq!(content(&local_conn, DOCUMENT).await, None);
+            assert!(is_deleted(&local_conn, DOCUMENT).await);
+        });
+    }
+
+    #[test]
+    fn book_files() {
+        with_mirror("mirror-books", |local_conn, root| async move {
+            let dir = root.join(BOOK);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(BOOK_FILE), "---\nname: \"Runbooks\"\n---\n").unwrap();
+            assert!(import_path(&local_conn, &root, &dir.join(BOOK_FILE))
+                .await
+                .unwrap());
+            let document = dir.join(format!("{DOCUMENT}.md"));
+            fs::write(&document, "# Hello").unwrap();
+            assert!(import_path(&local_conn, &root, &document).await.unwrap());
+
+            fs::write(dir.join(BOOK_FILE), "---\nname: \"Playbooks\"\n---\n").unwrap();
+            assert!(import_path(&local_conn, &root, &dir.join(BOOK_FILE))
+                .await
+                .unwrap());
+            let name: Option<String> = sqlx::query_scalar("SELECT name FROM books WHERE id = ?")
+                .bind(BOOK)
+                .fetch_one(&local_conn)
+                .await
+                .unwrap();
+            assert_eq!(name.as_deref(), Some("Playbooks"));
+
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(import_path(&local_conn, &root, &dir).await.unwrap());
+            assert!(is_deleted(&local_conn, BOOK).await);
+            assert!(is_deleted(&local_conn, DOCUMENT).await);
+
+            // NOTE: Books every library has aren't deleted with their directories
+            fs::remove_dir_all(root.join("default")).unwrap();
+            let default = root.join("default");
+            assert!(!import_path(&local_conn, &root, &default).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn invalid_names() {
+        with_mirror("mirror-invalid", |local_conn, root| async move {
+            for path in [
+                root.join("default").join("notes.md"),
+                root.join("default")
+                    .join(format!("{}.md", DOCUMENT.to_uppercase())),
+                root.join("default").join(format!("{DOCUMENT}.pdf")),
+                root.join("..evil").join(format!("{DOCUMENT}.md")),
+                root.join("Runbooks").join(format!("{DOCUMENT}.md")),
+            ] {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, "# Hello").unwrap();
+                assert!(!import_path(&local_conn, &root, &path).await.unwrap());
+            }
+            let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM documents")
+                .fetch_one(&local_conn)
+                .await
+                .unwrap();
+            assert_eq!(count, 0);
+        });
+    }
+
+    #[test]
+    fn edits_in_the_same_second() {
+        with_mirror("mirror-export", |local_conn, root| async move {
+            sqlx::query(
+                "INSERT INTO documents (id, book, name, content) VALUES (?, 'default', 'n', 'one')",
+            )
+            .bind(DOCUMENT)
+            .execute(&local_conn)
+            .await
+            .unwrap();
+            let mut exported = Exported::default();
+            let path = root.join("default").join(format!("{DOCUMENT}.md"));
+            for content in ["two", "three", "four"] {
+                sqlx::query("UPDATE documents SET content = ? WHERE id = ?")
+                    .bind(content)
+                    .bind(DOCUMENT)
+                    .execute(&local_conn)
+                    .await
+                    .unwrap();
+                export(&local_conn, &root, &mut exported).await.unwrap();
+                assert!(fs::read_to_string(&path).unwrap().ends_with(content));
+            }
+        });
+    }
+}
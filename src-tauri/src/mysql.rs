@@ -1,6 +1,29 @@
 // Copyright (C) 2025  Athan Clark
-use crate::types::{Book, Document, Id, IdAndModified};
+use crate::batches::{document_size, Batch, ID_CHUNK_SIZE};
+use crate::compression::{compress_document, decompress_document, COMPRESSION_METADATA, ZSTD};
+use crate::crypto::{decrypt_book, decrypt_document, encrypt_book, encrypt_document, LibraryKey};
+use crate::error::{ScriptoriumError, SyncError};
+use crate::hashes::{hash_local_documents, reconcile_local_documents, same_content};
+use crate::merkle::{
+    differing_buckets, in_buckets, local_buckets, remote_buckets, ROW_HASH_DIGITS,
+};
+use crate::outbox::Outbox;
+use crate::publish::{publish_diff, published_books, published_documents, render_document};
+use crate::schema::{drift_error, prefix_tables, schema_drift, ColumnInfo, MYSQL_SCHEMA};
+use crate::shares::{
+    delete_local_documents, local_book, local_book_modified, local_deletions,
+    local_documents_modified, local_documents_query, local_shared_ids, shared_error,
+    upsert_local_books, upsert_local_documents,
+};
+use crate::snapshots::restore_snapshot;
+use crate::types::{
+    Book, BookShare, BucketHash, Direction, Document, Grant, Id, IdAndModified, IdModifiedAndHash,
+    KeyCheck, Snapshot, ValueString,
+};
+use crate::verify::{compare_inventories, local_inventory, Divergence, Inventory};
 use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use log::warn;
 use sqlx::{
     migrate::{Migrate, Migrator},
     mysql::{MySqlConnectOptions, MySqlPool},
@@ -13,43 +36,65 @@ use std::collections::{HashMap, HashSet};
 pub async fn actually_sync_databases_mysql(
     local_conn: &Pool<Sqlite>,
     remote_conn: &Pool<MySql>,
-) -> Result<bool, String> {
+    prefix: &str,
+    owner: &str,
+    key: Option<&LibraryKey>,
+    outbox: &Outbox,
+    direction: Direction,
+) -> Result<bool, SyncError> {
     let mut has_modified = false;
+    if direction.pushes() {
+        register_owner_mysql(remote_conn, prefix, owner).await?;
+    }
+    let compress = remote_compression_mysql(remote_conn, prefix).await?;
+    let shares: Vec<BookShare> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT owner, book, permission FROM book_shares WHERE grantee = ?",
+    ))
+    .bind(owner)
+    .fetch_all(remote_conn)
+    .await?;
+    let (shared_books, shared_documents) = local_shared_ids(local_conn, &shares).await?;
 
+    let all_local_deletions: HashSet<String> = local_deletions(local_conn).await?;
+    let all_remote_deletions: HashSet<String> = sqlx::query_as::<_, Id>(&prefix_tables(
+        prefix,
+        "SELECT id FROM deleted WHERE owner = ?",
+    ))
+    .bind(owner)
+    .fetch(remote_conn)
+    .map_ok(|kv| kv.id)
+    .try_collect()
+    .await?;
     {
         // NOTE: Sync Deleted Books /////////////////////////////////
-        let all_local_deletions: Vec<Id> = sqlx::query_as("SELECT id FROM deleted")
-            .fetch_all(local_conn)
-            .await
-            .map_err(|e| e.to_string())?;
-        let all_remote_deletions: Vec<Id> = sqlx::query_as("SELECT id FROM deleted")
-            .fetch_all(remote_conn)
-            .await
-            .map_err(|e| e.to_string())?;
-        let all_local_deletions: HashSet<String> =
-            all_local_deletions.into_iter().map(|kv| kv.id).collect();
-        let all_remote_deletions: HashSet<String> =
-            all_remote_deletions.into_iter().map(|kv| kv.id).collect();
-        let local_to_delete: HashSet<&String> = all_remote_deletions
+        let mut local_to_delete: HashSet<&String> = all_remote_deletions
             .difference(&all_local_deletions)
             .collect();
-        let remote_to_delete: HashSet<&String> = all_local_deletions
+        let mut remote_to_delete: HashSet<&String> = all_local_deletions
             .difference(&all_remote_deletions)
             .collect();
+        // NOTE: Deletions aren't synced towards a side that's never changed, and the rows they
+        // remove aren't restored from it either -- they're left out until it's synced both ways
+        if !direction.pulls() {
+            local_to_delete.clear();
+        }
+        if !direction.pushes() {
+            remote_to_delete.clear();
+        }
+        // NOTE: A secondary's tombstones are made to match too, so it can't delete anything later
+        if direction.overwrites() {
+            let stale: Vec<String> = all_remote_deletions
+                .difference(&all_local_deletions)
+                .cloned()
+                .collect();
+            has_modified = delete_remote_rows_mysql(remote_conn, prefix, owner, "deleted", &stale)
+                .await?
+                || has_modified;
+        }
 
         if !local_to_delete.is_empty() {
             // NOTE: Remove from local first
-            let mut add_to_delete_table = QueryBuilder::<Sqlite>::new("INSERT INTO deleted (id)");
-            add_to_delete_table.push_values(local_to_delete.clone(), |mut builder, to_delete| {
-                has_modified = true;
-                builder.push_bind(to_delete);
-            });
-            add_to_delete_table
-                .build()
-                .execute(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
-
             let mut remove_from_documents =
                 QueryBuilder::<Sqlite>::new("DELETE FROM documents WHERE id IN (");
             let mut sep = remove_from_documents.separated(", ");
@@ -59,35 +104,45 @@ pub async fn actually_sync_databases_mysql(
             }
             sep.push_unseparated(")");
             let query = remove_from_documents.build();
-            query.execute(local_conn).await.map_err(|e| e.to_string())?;
+            query.execute(local_conn).await?;
 
             let mut remove_from_books =
                 QueryBuilder::<Sqlite>::new("DELETE FROM books WHERE id IN (");
             let mut sep = remove_from_books.separated(", ");
-            for id in local_to_delete {
+            for id in local_to_delete.clone() {
                 has_modified = true;
                 sep.push_bind(id);
             }
             sep.push_unseparated(")");
             let query = remove_from_books.build();
-            query.execute(local_conn).await.map_err(|e| e.to_string())?;
+            query.execute(local_conn).await?;
+
+            // NOTE: The delete triggers already add the rows that existed locally
+            let mut add_to_delete_table =
+                QueryBuilder::<Sqlite>::new("INSERT OR IGNORE INTO deleted (id)");
+            add_to_delete_table.push_values(local_to_delete, |mut builder, to_delete| {
+                builder.push_bind(to_delete);
+            });
+            add_to_delete_table.build().execute(local_conn).await?;
         }
 
         if !remote_to_delete.is_empty() {
             // NOTE: Remove remote second
-            let mut add_to_delete_table = QueryBuilder::<MySql>::new("INSERT INTO deleted (id)");
+            let mut add_to_delete_table = QueryBuilder::<MySql>::new(prefix_tables(
+                prefix,
+                "INSERT INTO deleted (owner, id)",
+            ));
             add_to_delete_table.push_values(remote_to_delete.clone(), |mut builder, to_delete| {
                 has_modified = true;
-                builder.push_bind(to_delete);
+                builder.push_bind(owner).push_bind(to_delete);
             });
-            add_to_delete_table
-                .build()
-                .execute(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+            add_to_delete_table.build().execute(remote_conn).await?;
 
-            let mut remove_from_documents =
-                QueryBuilder::<MySql>::new("DELETE FROM documents WHERE id IN (");
+            let mut remove_from_documents = QueryBuilder::<MySql>::new(prefix_tables(
+                prefix,
+                "DELETE FROM documents WHERE owner = ",
+            ));
+            remove_from_documents.push_bind(owner).push(" AND id IN (");
             let mut sep = remove_from_documents.separated(", ");
             for id in remote_to_delete.clone() {
                 has_modified = true;
@@ -95,13 +150,13 @@ pub async fn actually_sync_databases_mysql(
             }
             sep.push_unseparated(")");
             let query = remove_from_documents.build();
-            query
-                .execute(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+            query.execute(remote_conn).await?;
 
-            let mut remove_from_books =
-                QueryBuilder::<MySql>::new("DELETE FROM books WHERE id IN (");
+            let mut remove_from_books = QueryBuilder::<MySql>::new(prefix_tables(
+                prefix,
+                "DELETE FROM books WHERE owner = ",
+            ));
+            remove_from_books.push_bind(owner).push(" AND id IN (");
             let mut sep = remove_from_books.separated(", ");
             for id in remote_to_delete {
                 has_modified = true;
@@ -109,264 +164,1164 @@ pub async fn actually_sync_databases_mysql(
             }
             sep.push_unseparated(")");
             let query = remove_from_books.build();
-            query
-                .execute(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+            query.execute(remote_conn).await?;
         }
     }
 
     {
         // NOTE: Sync Existing Books ///////////////////////////////
-        let all_local_books: Vec<IdAndModified> = sqlx::query_as("SELECT id, modified FROM books")
-            .fetch_all(local_conn)
-            .await
-            .map_err(|e| e.to_string())?;
-        let all_remote_books: Vec<IdAndModified> = sqlx::query_as("SELECT id, modified FROM books")
-            .fetch_all(remote_conn)
-            .await
-            .map_err(|e| e.to_string())?;
-        let all_local_books: HashMap<String, DateTime<Utc>> = all_local_books
-            .into_iter()
-            .map(|kv| (kv.id, kv.modified))
-            .collect();
-        let all_remote_books: HashMap<String, DateTime<Utc>> = all_remote_books
-            .into_iter()
-            .map(|kv| (kv.id, kv.modified))
-            .collect();
-        let mut upsert_to_local: HashSet<String> = HashSet::new();
+        let mut all_local_books: HashMap<String, DateTime<Utc>> =
+            sqlx::query_as::<_, IdAndModified>("SELECT id, modified FROM books")
+                .fetch(local_conn)
+                .map_ok(|kv| (kv.id, kv.modified))
+                .try_collect()
+                .await?;
+        let all_remote_books: HashMap<String, DateTime<Utc>> = sqlx::query_as::<_, IdAndModified>(
+            &prefix_tables(prefix, "SELECT id, modified FROM books WHERE owner = ?"),
+        )
+        .bind(owner)
+        .fetch(remote_conn)
+        .map_ok(|kv| (kv.id, kv.modified))
+        .try_collect()
+        .await?;
+        // NOTE: Books shared with this user are synced with their owner's rows, below
+        all_local_books.retain(|id, _| !shared_books.contains(id));
+        if direction.overwrites() {
+            let remote_only: Vec<String> = all_remote_books
+                .keys()
+                .filter(|id| !all_local_books.contains_key(*id))
+                .cloned()
+                .collect();
+            has_modified =
+                delete_remote_rows_mysql(remote_conn, prefix, owner, "books", &remote_only).await?
+                    || has_modified;
+        }
+        let mut upsert_to_local: Vec<String> = vec![];
         for (remote_id, remote_modified) in &all_remote_books {
             match all_local_books.get(remote_id) {
                 None => {
-                    upsert_to_local.insert(remote_id.clone());
+                    upsert_to_local.push(remote_id.clone());
                 }
                 Some(local_modified) if remote_modified > local_modified => {
-                    upsert_to_local.insert(remote_id.clone());
+                    upsert_to_local.push(remote_id.clone());
                 }
                 _ => {}
             }
         }
-        let mut upsert_to_remote: HashSet<String> = HashSet::new();
-        for (local_id, local_modified) in all_local_books.into_iter() {
+        let mut upsert_to_remote: Vec<String> = vec![];
+        for (local_id, local_modified) in all_local_books
+            .into_iter()
+            .filter(|(id, _)| outbox.book_changed(id))
+        {
             match all_remote_books.get(&local_id) {
                 None => {
-                    upsert_to_remote.insert(local_id);
+                    upsert_to_remote.push(local_id);
                 }
-                Some(remote_modified) if local_modified > *remote_modified => {
-                    upsert_to_remote.insert(local_id);
+                Some(remote_modified) if direction.replaces(local_modified, *remote_modified) => {
+                    upsert_to_remote.push(local_id);
                 }
                 _ => {}
             }
         }
+        upsert_to_local.retain(|id| direction.pulls() && !all_local_deletions.contains(id));
+        upsert_to_remote.retain(|id| {
+            direction.pushes() && (direction.overwrites() || !all_remote_deletions.contains(id))
+        });
         if !upsert_to_local.is_empty() {
-            // TODO: query all fields from remote books that are slated to be upserted in local db
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE id IN (",
-            );
-            let mut sep = query_builder.separated(", ");
-            for id in upsert_to_local.into_iter() {
-                sep.push_bind(id);
+            let mut batch = Batch::default();
+            for ids in upsert_to_local.chunks(ID_CHUNK_SIZE) {
+                let mut query_builder = QueryBuilder::<MySql>::new(prefix_tables(
+                    prefix,
+                    "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE owner = ",
+                ));
+                query_builder.push_bind(owner).push(" AND id IN (");
+                let mut sep = query_builder.separated(", ");
+                for id in ids {
+                    sep.push_bind(id);
+                }
+                sep.push_unseparated(") ");
+                let mut rows = query_builder.build_query_as::<Book>().fetch(remote_conn);
+                while let Some(book) = rows.try_next().await? {
+                    if let Some(books) = batch.push(decrypt_book(key, book)?, 0) {
+                        upsert_local_books(local_conn, books).await?;
+                        has_modified = true;
+                    }
+                }
             }
-            sep.push_unseparated(") ");
-
-            let books: Vec<Book> = query_builder
-                .build_query_as()
-                .fetch_all(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
-
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "INSERT INTO books (id, name, modified, icon, icon_color, trash) ",
-            );
-            query_builder.push_values(books, |mut sep, row| {
-                sep.push_bind(row.id)
-                    .push_bind(row.name)
-                    .push_bind(row.modified)
-                    .push_bind(row.icon)
-                    .push_bind(row.icon_color)
-                    .push_bind(row.trash);
+            if let Some(books) = batch.finish() {
+                upsert_local_books(local_conn, books).await?;
                 has_modified = true;
-            });
-            query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, modified = EXCLUDED.modified, icon = EXCLUDED.icon, icon_color = EXCLUDED.icon_color, trash = EXCLUDED.trash");
-
-            query_builder
-                .build()
-                .execute(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+            }
         }
         if !upsert_to_remote.is_empty() {
-            // TODO: query all fields from local books that are slated to be upserted in remote db
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE id IN (",
-            );
-            let mut sep = query_builder.separated(", ");
-            for id in upsert_to_remote.into_iter() {
-                sep.push_bind(id);
+            let mut batch = Batch::default();
+            for ids in upsert_to_remote.chunks(ID_CHUNK_SIZE) {
+                let mut query_builder = QueryBuilder::<Sqlite>::new(
+                    "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE id IN (",
+                );
+                let mut sep = query_builder.separated(", ");
+                for id in ids {
+                    sep.push_bind(id);
+                }
+                sep.push_unseparated(") ");
+                let mut rows = query_builder.build_query_as::<Book>().fetch(local_conn);
+                while let Some(book) = rows.try_next().await? {
+                    if let Some(books) = batch.push(encrypt_book(key, book)?, 0) {
+                        upsert_remote_books_mysql(remote_conn, prefix, owner, books, false).await?;
+                        has_modified = true;
+                    }
+                }
             }
-            sep.push_unseparated(") ");
-
-            let books: Vec<Book> = query_builder
-                .build_query_as()
-                .fetch_all(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
-
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "INSERT INTO books (id, name, modified, icon, icon_color, trash) ",
-            );
-            query_builder.push_values(books, |mut sep, row| {
-                sep.push_bind(row.id)
-                    .push_bind(row.name)
-                    .push_bind(row.modified)
-                    .push_bind(row.icon)
-                    .push_bind(row.icon_color)
-                    .push_bind(row.trash);
+            if let Some(books) = batch.finish() {
+                upsert_remote_books_mysql(remote_conn, prefix, owner, books, false).await?;
                 has_modified = true;
-            });
-            // FIXME: PostgreSQL and SQLite will use `EXCLUDED` instead of `new`
-            // FIXME: MariaDB uses `Values(name, modified, ...)` deprecated syntax -- will have to
-            // support explicitly
-            // FIXME: Also, `ON CONFLICT (id)` is only available on postgresql and sqlite
-            // query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = new.name, modified = new.modified, icon = new.icon, icon_color = new.icon_color, trash = new.trash");
-            query_builder.push(" AS new ON DUPLICATE KEY UPDATE name = new.name, modified = new.modified, icon = new.icon, icon_color = new.icon_color, trash = new.trash");
-
-            query_builder
-                .build()
-                .execute(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+            }
         }
     }
 
     {
         // NOTE: Sync Existing Documents ///////////////////////////////
-        let all_local_documents: Vec<IdAndModified> =
-            sqlx::query_as("SELECT id, modified FROM documents")
-                .fetch_all(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
-        let all_remote_documents: Vec<IdAndModified> =
-            sqlx::query_as("SELECT id, modified FROM documents")
-                .fetch_all(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
-        let all_local_documents: HashMap<String, DateTime<Utc>> = all_local_documents
-            .into_iter()
-            .map(|kv| (kv.id, kv.modified))
-            .collect();
-        let all_remote_documents: HashMap<String, DateTime<Utc>> = all_remote_documents
-            .into_iter()
-            .map(|kv| (kv.id, kv.modified))
-            .collect();
-        let mut upsert_to_local: HashSet<String> = HashSet::new();
-        for (remote_id, remote_modified) in &all_remote_documents {
+        hash_local_documents(local_conn).await?;
+        let mut all_local_documents: HashMap<String, IdModifiedAndHash> =
+            sqlx::query_as::<_, IdModifiedAndHash>(
+                "SELECT id, modified, content_hash FROM documents",
+            )
+            .fetch(local_conn)
+            .map_ok(|kv| (kv.id.clone(), kv))
+            .try_collect()
+            .await?;
+        all_local_documents.retain(|id, _| !shared_documents.contains(id));
+        let (differing, all_remote_documents) =
+            differing_documents_mysql(remote_conn, prefix, owner, &all_local_documents).await?;
+        if direction.overwrites() {
+            let remote_only: Vec<String> = all_remote_documents
+                .keys()
+                .filter(|id| !all_local_documents.contains_key(*id))
+                .cloned()
+                .collect();
+            has_modified =
+                delete_remote_rows_mysql(remote_conn, prefix, owner, "documents", &remote_only)
+                    .await?
+                    || has_modified;
+        }
+        // NOTE: Documents whose hashes match only differ in when they were modified, so only the
+        // older side's timestamp is moved forward
+        let mut upsert_to_local: Vec<String> = vec![];
+        let mut reconcile_local: Vec<(String, DateTime<Utc>)> = vec![];
+        for (remote_id, remote) in &all_remote_documents {
             match all_local_documents.get(remote_id) {
                 None => {
-                    upsert_to_local.insert(remote_id.clone());
+                    upsert_to_local.push(remote_id.clone());
                 }
-                Some(local_modified) if remote_modified > local_modified => {
-                    upsert_to_local.insert(remote_id.clone());
+                Some(local)
+                    if remote.modified > local.modified
+                        && same_content(&local.content_hash, &remote.content_hash) =>
+                {
+                    reconcile_local.push((remote_id.clone(), remote.modified));
+                }
+                Some(local) if remote.modified > local.modified => {
+                    upsert_to_local.push(remote_id.clone());
                 }
                 _ => {}
             }
         }
-        let mut upsert_to_remote: HashSet<String> = HashSet::new();
-        for (local_id, local_modified) in all_local_documents.into_iter() {
+        let mut upsert_to_remote: Vec<String> = vec![];
+        let mut reconcile_remote: Vec<(String, DateTime<Utc>)> = vec![];
+        for (local_id, local) in all_local_documents
+            .into_iter()
+            .filter(|(id, _)| in_buckets(id, &differing) && outbox.document_changed(id))
+        {
             match all_remote_documents.get(&local_id) {
                 None => {
-                    upsert_to_remote.insert(local_id);
+                    upsert_to_remote.push(local_id);
+                }
+                Some(remote)
+                    if direction.replaces(local.modified, remote.modified)
+                        && same_content(&local.content_hash, &remote.content_hash) =>
+                {
+                    reconcile_remote.push((local_id, local.modified));
                 }
-                Some(remote_modified) if local_modified > *remote_modified => {
-                    upsert_to_remote.insert(local_id);
+                Some(remote) if direction.replaces(local.modified, remote.modified) => {
+                    upsert_to_remote.push(local_id);
                 }
                 _ => {}
             }
         }
+        upsert_to_local.retain(|id| direction.pulls() && !all_local_deletions.contains(id));
+        upsert_to_remote.retain(|id| {
+            direction.pushes() && (direction.overwrites() || !all_remote_deletions.contains(id))
+        });
+        if !direction.pulls() {
+            reconcile_local.clear();
+        }
+        if !direction.pushes() {
+            reconcile_remote.clear();
+        }
+        reconcile_local_documents(local_conn, &reconcile_local).await?;
+        for (id, modified) in reconcile_remote {
+            sqlx::query(&prefix_tables(
+                prefix,
+                "UPDATE documents SET modified = ? WHERE owner = ? AND id = ?",
+            ))
+            .bind(modified)
+            .bind(owner)
+            .bind(id)
+            .execute(remote_conn)
+            .await?;
+        }
         if !upsert_to_local.is_empty() {
-            // TODO: query all fields from remote documents that are slated to be upserted in local db
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE id IN ("
-            );
+            let mut batch = Batch::default();
+            for ids in upsert_to_local.chunks(ID_CHUNK_SIZE) {
+                let mut query_builder = QueryBuilder::<MySql>::new(
+                    prefix_tables(prefix, "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE owner = ")
+                );
+                query_builder.push_bind(owner).push(" AND id IN (");
+                let mut sep = query_builder.separated(", ");
+                for id in ids {
+                    sep.push_bind(id);
+                }
+                sep.push_unseparated(") ");
+                let mut rows = query_builder
+                    .build_query_as::<Document>()
+                    .fetch(remote_conn);
+                while let Some(document) = rows.try_next().await? {
+                    let document = decrypt_document(key, document).and_then(decompress_document)?;
+                    let size = document_size(&document);
+                    if let Some(documents) = batch.push(document, size) {
+                        upsert_local_documents(local_conn, documents).await?;
+                        has_modified = true;
+                    }
+                }
+            }
+            if let Some(documents) = batch.finish() {
+                upsert_local_documents(local_conn, documents).await?;
+                has_modified = true;
+            }
+        }
+        if !upsert_to_remote.is_empty() {
+            let mut batch = Batch::default();
+            for ids in upsert_to_remote.chunks(ID_CHUNK_SIZE) {
+                let mut query_builder = local_documents_query(ids);
+                let mut rows = query_builder.build_query_as::<Document>().fetch(local_conn);
+                while let Some(document) = rows.try_next().await? {
+                    let document = compress_document(compress, document)
+                        .and_then(|document| encrypt_document(key, document))?;
+                    let size = document_size(&document);
+                    if let Some(documents) = batch.push(document, size) {
+                        upsert_remote_documents_mysql(remote_conn, prefix, owner, documents)
+                            .await?;
+                        has_modified = true;
+                    }
+                }
+            }
+            if let Some(documents) = batch.finish() {
+                upsert_remote_documents_mysql(remote_conn, prefix, owner, documents).await?;
+                has_modified = true;
+            }
+        }
+    }
+
+    for share in &shares {
+        has_modified =
+            sync_shared_book_mysql(local_conn, remote_conn, prefix, share, compress, direction)
+                .await?
+                || has_modified;
+    }
+
+    Ok(has_modified) // NOTE: return if changes were made
+}
+
+/// Deletes the owner's rows with the given ids from `table`. Returns whether there were any.
+async fn delete_remote_rows_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    table: &str,
+    ids: &[String],
+) -> Result<bool, SyncError> {
+    for ids in ids.chunks(ID_CHUNK_SIZE) {
+        let mut query_builder = QueryBuilder::<MySql>::new(prefix_tables(
+            prefix,
+            &format!("DELETE FROM {table} WHERE owner = "),
+        ));
+        query_builder.push_bind(owner).push(" AND id IN (");
+        let mut sep = query_builder.separated(", ");
+        for id in ids {
+            sep.push_bind(id);
+        }
+        sep.push_unseparated(")");
+        query_builder.build().execute(remote_conn).await?;
+    }
+    Ok(!ids.is_empty())
+}
+
+/// Adds the user syncing to the remote's users, so it can be found by name.
+async fn register_owner_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+) -> Result<(), SyncError> {
+    sqlx::query(&prefix_tables(
+        prefix,
+        "INSERT IGNORE INTO users (name) VALUES (?)",
+    ))
+    .bind(owner)
+    .execute(remote_conn)
+    .await?;
+    Ok(())
+}
+
+/// Upserts a batch of books as the owner's rows.
+async fn upsert_remote_books_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    books: Vec<Book>,
+    published: bool,
+) -> Result<(), SyncError> {
+    let mut query_builder = QueryBuilder::<MySql>::new(prefix_tables(
+        prefix,
+        "INSERT INTO books (owner, id, name, modified, icon, icon_color, trash, published) ",
+    ));
+    query_builder.push_values(books, |mut sep, row| {
+        sep.push_bind(owner)
+            .push_bind(row.id)
+            .push_bind(row.name)
+            .push_bind(row.modified)
+            .push_bind(row.icon)
+            .push_bind(row.icon_color)
+            .push_bind(row.trash)
+            .push_bind(published);
+    });
+    // FIXME: PostgreSQL and SQLite will use `EXCLUDED` instead of `new`
+    // FIXME: MariaDB uses `Values(name, modified, ...)` deprecated syntax -- will have to
+    // support explicitly
+    // FIXME: Also, `ON CONFLICT (id)` is only available on postgresql and sqlite
+    // query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = new.name, modified = new.modified, icon = new.icon, icon_color = new.icon_color, trash = new.trash");
+    query_builder.push(" AS new ON DUPLICATE KEY UPDATE name = new.name, modified = new.modified, icon = new.icon, icon_color = new.icon_color, trash = new.trash, published = new.published");
+    query_builder.build().execute(remote_conn).await?;
+    Ok(())
+}
+
+/// Upserts a batch of documents as the owner's rows.
+async fn upsert_remote_documents_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    documents: Vec<Document>,
+) -> Result<(), SyncError> {
+    let mut query_builder = QueryBuilder::<MySql>::new(
+        prefix_tables(prefix, "INSERT INTO documents (owner, id, book, name, modified, content, syntax, icon, icon_color, content_hash, rendered) "),
+    );
+    query_builder.push_values(documents, |mut sep, row| {
+        sep.push_bind(owner)
+            .push_bind(row.id)
+            .push_bind(row.book)
+            .push_bind(row.name)
+            .push_bind(row.modified)
+            .push_bind(row.content)
+            .push_bind(row.syntax)
+            .push_bind(row.icon)
+            .push_bind(row.icon_color)
+            .push_bind(row.content_hash)
+            .push_bind(row.rendered);
+    });
+    // FIXME: PostgreSQL and SQLite will use `EXCLUDED` instead of `new`
+    // FIXME: MariaDB uses `Values(name, modified, ...)` deprecated syntax -- will have to
+    // support explicitly
+    // FIXME: Also, `ON CONFLICT (id)` is only available on postgresql and sqlite
+    // query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = new.name, book = new.book, modified = new.modified, content = new.content, syntax = new.syntax, icon = new.icon, icon_color = new.icon_color");
+    query_builder.push(" AS new ON DUPLICATE KEY UPDATE name = new.name, book = new.book, modified = new.modified, content = new.content, syntax = new.syntax, icon = new.icon, icon_color = new.icon_color, content_hash = new.content_hash, rendered = new.rendered");
+    query_builder.build().execute(remote_conn).await?;
+    Ok(())
+}
+
+/// Finds where the owner's documents differ from the local ones, by comparing buckets of ids
+/// top-down and only reading the rows of the buckets that differ. Returns those buckets, and the
+/// remote's rows in them.
+async fn differing_documents_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    local_documents: &HashMap<String, IdModifiedAndHash>,
+) -> Result<(HashSet<String>, HashMap<String, IdModifiedAndHash>), SyncError> {
+    let mut leaves: Vec<String> = vec![];
+    let mut parents: Option<HashSet<String>> = None;
+    let mut length = 1;
+    loop {
+        let mut query_builder = QueryBuilder::<MySql>::new(prefix_tables(
+            prefix,
+            &format!(
+                "SELECT LEFT(id, {length}) AS bucket, COUNT(*) AS count, CAST(SUM(CAST(CONV(LEFT(SHA2(CONCAT(id, ':', FLOOR(UNIX_TIMESTAMP(modified))), 256), {ROW_HASH_DIGITS}), 16, 10) AS UNSIGNED)) AS CHAR) AS sum FROM documents WHERE owner = "
+            ),
+        ));
+        query_builder.push_bind(owner);
+        if let Some(parents) = &parents {
+            query_builder.push(format!(" AND LEFT(id, {}) IN (", length - 1));
             let mut sep = query_builder.separated(", ");
-            for id in upsert_to_local.into_iter() {
-                sep.push_bind(id);
+            for parent in parents {
+                sep.push_bind(parent);
             }
-            sep.push_unseparated(") ");
+            sep.push_unseparated(")");
+        }
+        query_builder.push(" GROUP BY 1");
+        let remote: Vec<BucketHash> = query_builder
+            .build_query_as()
+            .fetch_all(remote_conn)
+            .await?;
+        let remote = remote_buckets(remote)?;
+        let local = local_buckets(local_documents, parents.as_ref(), length);
+        let split = differing_buckets(&local, &remote, length, &mut leaves);
+        if split.is_empty() {
+            break;
+        }
+        parents = Some(split);
+        length += 1;
+    }
 
-            let documents: Vec<Document> = query_builder
-                .build_query_as()
-                .fetch_all(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+    let mut by_length: HashMap<usize, Vec<&String>> = HashMap::new();
+    for leaf in &leaves {
+        by_length
+            .entry(leaf.chars().count())
+            .or_default()
+            .push(leaf);
+    }
+    let mut documents: HashMap<String, IdModifiedAndHash> = HashMap::new();
+    for (length, buckets) in by_length {
+        for buckets in buckets.chunks(ID_CHUNK_SIZE) {
+            let mut query_builder = QueryBuilder::<MySql>::new(prefix_tables(
+                prefix,
+                "SELECT id, modified, content_hash FROM documents WHERE owner = ",
+            ));
+            query_builder
+                .push_bind(owner)
+                .push(format!(" AND LEFT(id, {length}) IN ("));
+            let mut sep = query_builder.separated(", ");
+            for bucket in buckets {
+                sep.push_bind(*bucket);
+            }
+            sep.push_unseparated(")");
+            let mut rows = query_builder
+                .build_query_as::<IdModifiedAndHash>()
+                .fetch(remote_conn);
+            while let Some(row) = rows.try_next().await? {
+                documents.insert(row.id.clone(), row);
+            }
+        }
+    }
+    Ok((leaves.into_iter().collect(), documents))
+}
 
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "INSERT INTO documents (id, book, name, modified, content, syntax, icon, icon_color) "
-            );
-            query_builder.push_values(documents, |mut sep, row| {
-                sep.push_bind(row.id)
-                    .push_bind(row.book)
-                    .push_bind(row.name)
-                    .push_bind(row.modified)
-                    .push_bind(row.content)
-                    .push_bind(row.syntax)
-                    .push_bind(row.icon)
-                    .push_bind(row.icon_color);
+/// Whether new document content is pushed compressed, as recorded in the remote's metadata.
+pub async fn remote_compression_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+) -> Result<bool, SyncError> {
+    let compression: Option<ValueString> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT value FROM metadata WHERE name = ?",
+    ))
+    .bind(COMPRESSION_METADATA)
+    .fetch_optional(remote_conn)
+    .await?;
+    Ok(compression.is_some_and(|c| c.value == ZSTD))
+}
+
+/// Turns compression of new document content on or off, for every device syncing with the remote.
+/// Content that's already there stays as it is until it's pushed again.
+pub async fn set_remote_compression_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    compress: bool,
+) -> Result<(), SyncError> {
+    if compress {
+        sqlx::query(&prefix_tables(
+            prefix,
+            "INSERT INTO metadata (name, value) VALUES (?, ?) AS new ON DUPLICATE KEY UPDATE value = new.value",
+        ))
+        .bind(COMPRESSION_METADATA)
+        .bind(ZSTD)
+        .execute(remote_conn)
+        .await?;
+    } else {
+        sqlx::query(&prefix_tables(
+            prefix,
+            "DELETE FROM metadata WHERE name = ?",
+        ))
+        .bind(COMPRESSION_METADATA)
+        .execute(remote_conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Derives the owner's key for this remote from the passphrase. The first device to sync with a
+/// passphrase sets up the owner's salt and check value; every device after it has to use the same
+/// passphrase. Only a remote set to `encrypt` is set up this way -- once it is, the passphrase is
+/// needed whether or not it's still set to. Returns `None` when syncing in plaintext.
+pub async fn remote_key_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    passphrase: Option<&str>,
+    encrypt: bool,
+) -> Result<Option<LibraryKey>, SyncError> {
+    let key_check: Option<KeyCheck> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT salt, check_value FROM key_check WHERE owner = ? AND id = 1",
+    ))
+    .bind(owner)
+    .fetch_optional(remote_conn)
+    .await?;
+    match (key_check, passphrase) {
+        (None, _) if !encrypt => Ok(None),
+        (None, None) => Err(ScriptoriumError::Encryption {
+            message: "this remote is to be encrypted -- unlock the library first".to_string(),
+        }
+        .into()),
+        (Some(_), None) => Err(ScriptoriumError::Encryption {
+            message: "this remote is encrypted -- unlock the library first".to_string(),
+        }
+        .into()),
+        (Some(key_check), Some(passphrase)) => {
+            let key = LibraryKey::derive(passphrase, &key_check.salt)?;
+            key.verify(&key_check.check_value)?;
+            Ok(Some(key))
+        }
+        (None, Some(passphrase)) => {
+            // NOTE: Rows already pushed in plaintext would stay that way, since only changes are
+            // pushed
+            let rows: i64 = sqlx::query_scalar(&prefix_tables(prefix, "SELECT (SELECT COUNT(*) FROM books WHERE owner = ?) + (SELECT COUNT(*) FROM documents WHERE owner = ?) + (SELECT COUNT(*) FROM snapshots WHERE owner = ?)"))
+                .bind(owner)
+                .bind(owner)
+                .bind(owner)
+                .fetch_one(remote_conn)
+                .await?;
+            if rows > 0 {
+                return Err(ScriptoriumError::Encryption {
+                    message:
+                        "this remote already has the library unencrypted, so it can't be encrypted"
+                            .to_string(),
+                }
+                .into());
+            }
+            let salt = LibraryKey::new_salt();
+            let key = LibraryKey::derive(passphrase, &salt)?;
+            sqlx::query(&prefix_tables(
+                prefix,
+                "INSERT INTO key_check (owner, id, salt, check_value) VALUES (?, 1, ?, ?)",
+            ))
+            .bind(owner)
+            .bind(&salt)
+            .bind(key.check_value()?)
+            .execute(remote_conn)
+            .await?;
+            Ok(Some(key))
+        }
+    }
+}
+
+/// Compares the remote's columns against the ones the migrations should have left it with, under
+/// the remote's table prefix.
+pub async fn check_schema_mysql(remote_conn: &Pool<MySql>, prefix: &str) -> Result<(), SyncError> {
+    // NOTE: Cast, since MySQL 8 returns information_schema's names as binary strings
+    let columns: Vec<ColumnInfo> = sqlx::query_as(
+        "SELECT CAST(table_name AS CHAR) AS table_name, CAST(column_name AS CHAR) AS column_name, CAST(data_type AS CHAR) AS data_type, CAST(is_nullable AS CHAR) AS is_nullable FROM information_schema.columns WHERE table_schema = DATABASE()",
+    )
+    .fetch_all(remote_conn)
+    .await?;
+    // NOTE: Compared by their unprefixed names; other apps' tables are left out
+    let columns: Vec<ColumnInfo> = columns
+        .into_iter()
+        .filter_map(|c| {
+            Some(ColumnInfo {
+                table_name: c.table_name.strip_prefix(prefix)?.to_string(),
+                ..c
+            })
+        })
+        .collect();
+    Ok(drift_error(schema_drift(&MYSQL_SCHEMA, &columns))?)
+}
+
+/// Syncs a book shared with this user by its owner, as the owner's rows. Changes are only pushed
+/// with write permission; shared books are never encrypted.
+async fn sync_shared_book_mysql(
+    local_conn: &Pool<Sqlite>,
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    share: &BookShare,
+    compress: bool,
+    direction: Direction,
+) -> Result<bool, SyncError> {
+    let mut has_modified = false;
+    let writable = share.permission == "write";
+    let local_deletions = local_deletions(local_conn).await?;
+    let mut local_documents = local_documents_modified(local_conn, &share.book).await?;
+
+    // NOTE: Deletions made by the owner, or by anyone else it's shared with
+    let mut deleted_remotely: Vec<String> = sqlx::query_as::<_, Id>(&prefix_tables(
+        prefix,
+        "SELECT id FROM deleted WHERE owner = ?",
+    ))
+    .bind(&share.owner)
+    .fetch(remote_conn)
+    .map_ok(|d| d.id)
+    .try_collect()
+    .await?;
+    deleted_remotely.retain(|id| local_documents.contains_key(id));
+    if !deleted_remotely.is_empty() {
+        // NOTE: Without pulling, they're only left out of the sync
+        if direction.pulls() {
+            delete_local_documents(local_conn, &deleted_remotely).await?;
+            has_modified = true;
+        }
+        for id in &deleted_remotely {
+            local_documents.remove(id);
+        }
+    }
+
+    let remote_book: Option<Book> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE owner = ? AND id = ?",
+    ))
+    .bind(&share.owner)
+    .bind(&share.book)
+    .fetch_optional(remote_conn)
+    .await?;
+    let Some(remote_book) = remote_book else {
+        return Ok(has_modified);
+    };
+    let remote_book = decrypt_book(None, remote_book).map_err(|e| shared_error(share, e))?;
+    match local_book_modified(local_conn, &share.book).await? {
+        // NOTE: Deleted from this library, but not from the owner's
+        None if local_deletions.contains(&share.book) => return Ok(has_modified),
+        Some(local_modified) if local_modified > remote_book.modified => {
+            if writable && direction.pushes() {
+                let book = local_book(local_conn, &share.book).await?;
+                sqlx::query(&prefix_tables(prefix, "UPDATE books SET name = ?, modified = ?, icon = ?, icon_color = ?, trash = ? WHERE owner = ? AND id = ?"))
+                    .bind(book.name)
+                    .bind(book.modified)
+                    .bind(book.icon)
+                    .bind(book.icon_color)
+                    .bind(book.trash)
+                    .bind(&share.owner)
+                    .bind(&share.book)
+                    .execute(remote_conn)
+                    .await?;
                 has_modified = true;
-            });
-            query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, book = EXCLUDED.book, modified = EXCLUDED.modified, content = EXCLUDED.content, syntax = EXCLUDED.syntax, icon = EXCLUDED.icon, icon_color = EXCLUDED.icon_color");
+            } else if !writable {
+                warn!(
+                    "not pushing changes to {}, it's shared read-only",
+                    share.book
+                );
+            }
+        }
+        Some(local_modified) if local_modified == remote_book.modified => {}
+        _ if !direction.pulls() => {}
+        _ => {
+            upsert_local_books(local_conn, vec![remote_book]).await?;
+            has_modified = true;
+        }
+    }
 
-            query_builder
-                .build()
-                .execute(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+    let remote_documents: HashMap<String, IdModifiedAndHash> =
+        sqlx::query_as::<_, IdModifiedAndHash>(&prefix_tables(
+            prefix,
+            "SELECT id, modified, content_hash FROM documents WHERE owner = ? AND book = ?",
+        ))
+        .bind(&share.owner)
+        .bind(&share.book)
+        .fetch(remote_conn)
+        .map_ok(|kv| (kv.id.clone(), kv))
+        .try_collect()
+        .await?;
+    let mut pull: Vec<String> = vec![];
+    let mut push: Vec<String> = vec![];
+    let mut delete_remote: Vec<String> = vec![];
+    let mut reconcile_local: Vec<(String, DateTime<Utc>)> = vec![];
+    let mut reconcile_remote: Vec<(String, DateTime<Utc>)> = vec![];
+    for (remote_id, remote) in &remote_documents {
+        match local_documents.get(remote_id) {
+            None if local_deletions.contains(remote_id) => delete_remote.push(remote_id.clone()),
+            None => pull.push(remote_id.clone()),
+            Some(local) if remote.modified > local.modified => {
+                if same_content(&local.content_hash, &remote.content_hash) {
+                    reconcile_local.push((remote_id.clone(), remote.modified));
+                } else {
+                    pull.push(remote_id.clone());
+                }
+            }
+            Some(local) if local.modified > remote.modified => {
+                if same_content(&local.content_hash, &remote.content_hash) {
+                    reconcile_remote.push((remote_id.clone(), local.modified));
+                } else {
+                    push.push(remote_id.clone());
+                }
+            }
+            _ => {}
         }
-        if !upsert_to_remote.is_empty() {
-            // TODO: query all fields from local documents that are slated to be upserted in remote db
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE id IN ("
+    }
+    for local_id in local_documents.keys() {
+        if !remote_documents.contains_key(local_id) {
+            push.push(local_id.clone());
+        }
+    }
+    if !direction.pulls() {
+        pull.clear();
+        reconcile_local.clear();
+    }
+    if !writable || !direction.pushes() {
+        if !writable && (!push.is_empty() || !delete_remote.is_empty()) {
+            warn!(
+                "not pushing changes to {}, it's shared read-only",
+                share.book
             );
+        }
+        push.clear();
+        delete_remote.clear();
+        reconcile_remote.clear();
+    }
+
+    reconcile_local_documents(local_conn, &reconcile_local).await?;
+    for (id, modified) in reconcile_remote {
+        sqlx::query(&prefix_tables(
+            prefix,
+            "UPDATE documents SET modified = ? WHERE owner = ? AND id = ?",
+        ))
+        .bind(modified)
+        .bind(&share.owner)
+        .bind(id)
+        .execute(remote_conn)
+        .await?;
+    }
+    if !pull.is_empty() {
+        let mut batch = Batch::default();
+        for ids in pull.chunks(ID_CHUNK_SIZE) {
+            let mut query_builder = QueryBuilder::<MySql>::new(
+                prefix_tables(prefix, "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE owner = "),
+            );
+            query_builder.push_bind(&share.owner).push(" AND id IN (");
             let mut sep = query_builder.separated(", ");
-            for id in upsert_to_remote.into_iter() {
+            for id in ids {
                 sep.push_bind(id);
             }
-            sep.push_unseparated(") ");
+            sep.push_unseparated(")");
+            let mut rows = query_builder
+                .build_query_as::<Document>()
+                .fetch(remote_conn);
+            while let Some(document) = rows.try_next().await? {
+                let document = decrypt_document(None, document)
+                    .and_then(decompress_document)
+                    .map_err(|e| shared_error(share, e))?;
+                let size = document_size(&document);
+                if let Some(documents) = batch.push(document, size) {
+                    upsert_local_documents(local_conn, documents).await?;
+                    has_modified = true;
+                }
+            }
+        }
+        if let Some(documents) = batch.finish() {
+            upsert_local_documents(local_conn, documents).await?;
+            has_modified = true;
+        }
+    }
+    if !push.is_empty() {
+        let mut batch = Batch::default();
+        for ids in push.chunks(ID_CHUNK_SIZE) {
+            let mut query_builder = local_documents_query(ids);
+            let mut rows = query_builder.build_query_as::<Document>().fetch(local_conn);
+            while let Some(document) = rows.try_next().await? {
+                let document = compress_document(compress, document)?;
+                let size = document_size(&document);
+                if let Some(documents) = batch.push(document, size) {
+                    upsert_remote_documents_mysql(remote_conn, prefix, &share.owner, documents)
+                        .await?;
+                    has_modified = true;
+                }
+            }
+        }
+        if let Some(documents) = batch.finish() {
+            upsert_remote_documents_mysql(remote_conn, prefix, &share.owner, documents).await?;
+            has_modified = true;
+        }
+    }
+    if !delete_remote.is_empty() {
+        let mut add_to_delete_table =
+            QueryBuilder::<MySql>::new(prefix_tables(prefix, "INSERT INTO deleted (owner, id)"));
+        add_to_delete_table.push_values(&delete_remote, |mut builder, to_delete| {
+            builder.push_bind(&share.owner).push_bind(to_delete);
+        });
+        add_to_delete_table.build().execute(remote_conn).await?;
+        let mut remove_from_documents = QueryBuilder::<MySql>::new(prefix_tables(
+            prefix,
+            "DELETE FROM documents WHERE owner = ",
+        ));
+        remove_from_documents
+            .push_bind(&share.owner)
+            .push(" AND id IN (");
+        let mut sep = remove_from_documents.separated(", ");
+        for id in &delete_remote {
+            sep.push_bind(id);
+        }
+        sep.push_unseparated(")");
+        remove_from_documents.build().execute(remote_conn).await?;
+        has_modified = true;
+    }
 
-            let documents: Vec<Document> = query_builder
-                .build_query_as()
-                .fetch_all(local_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+    Ok(has_modified)
+}
 
-            let mut query_builder = sqlx::QueryBuilder::new(
-                "INSERT INTO documents (id, book, name, modified, content, syntax, icon, icon_color) "
-            );
-            query_builder.push_values(documents, |mut sep, row| {
-                sep.push_bind(row.id)
-                    .push_bind(row.book)
-                    .push_bind(row.name)
-                    .push_bind(row.modified)
-                    .push_bind(row.content)
-                    .push_bind(row.syntax)
-                    .push_bind(row.icon)
-                    .push_bind(row.icon_color);
-                has_modified = true;
-            });
-            // FIXME: PostgreSQL and SQLite will use `EXCLUDED` instead of `new`
-            // FIXME: MariaDB uses `Values(name, modified, ...)` deprecated syntax -- will have to
-            // support explicitly
-            // FIXME: Also, `ON CONFLICT (id)` is only available on postgresql and sqlite
-            // query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = new.name, book = new.book, modified = new.modified, content = new.content, syntax = new.syntax, icon = new.icon, icon_color = new.icon_color");
-            query_builder.push(" AS new ON DUPLICATE KEY UPDATE name = new.name, book = new.book, modified = new.modified, content = new.content, syntax = new.syntax, icon = new.icon, icon_color = new.icon_color");
+/// Publishes the books picked for `remote` one way: the owner's rows on the remote are made to
+/// match them exactly, with each document's rendered HTML next to it.
+pub async fn publish_books_mysql(
+    local_conn: &Pool<Sqlite>,
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    remote: &str,
+    owner: &str,
+) -> Result<bool, SyncError> {
+    let mut has_modified = false;
+    let key_check: Option<KeyCheck> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT salt, check_value FROM key_check WHERE owner = ? AND id = 1",
+    ))
+    .bind(owner)
+    .fetch_optional(remote_conn)
+    .await?;
+    if key_check.is_some() {
+        return Err(ScriptoriumError::Encryption {
+            message: "this remote is encrypted, so it can't be published to".to_string(),
+        }
+        .into());
+    }
+    // NOTE: Publishing deletes the owner's rows that aren't published, so it would delete a library
+    // synced to the remote
+    let unpublished: i64 = sqlx::query_scalar(&prefix_tables(prefix, "SELECT (SELECT COUNT(*) FROM books WHERE owner = ? AND NOT published) + (SELECT COUNT(*) FROM documents d WHERE d.owner = ? AND NOT EXISTS (SELECT 1 FROM books b WHERE b.owner = d.owner AND b.id = d.book AND b.published))"))
+        .bind(owner)
+        .bind(owner)
+        .fetch_one(remote_conn)
+        .await?;
+    if unpublished > 0 {
+        return Err(
+            "this remote has books that aren't published, so it can't be published to"
+                .to_string()
+                .into(),
+        );
+    }
+    register_owner_mysql(remote_conn, prefix, owner).await?;
 
-            query_builder
-                .build()
-                .execute(remote_conn)
-                .await
-                .map_err(|e| e.to_string())?;
+    let remote_books: HashMap<String, DateTime<Utc>> = sqlx::query_as::<_, IdAndModified>(
+        &prefix_tables(prefix, "SELECT id, modified FROM books WHERE owner = ?"),
+    )
+    .bind(owner)
+    .fetch(remote_conn)
+    .map_ok(|kv| (kv.id, kv.modified))
+    .try_collect()
+    .await?;
+    let remote_documents: HashMap<String, DateTime<Utc>> = sqlx::query_as::<_, IdAndModified>(
+        &prefix_tables(prefix, "SELECT id, modified FROM documents WHERE owner = ?"),
+    )
+    .bind(owner)
+    .fetch(remote_conn)
+    .map_ok(|kv| (kv.id, kv.modified))
+    .try_collect()
+    .await?;
+    let (remove_books, upsert_books) =
+        publish_diff(&published_books(local_conn, remote).await?, &remote_books);
+    let (remove_documents, upsert_documents) = publish_diff(
+        &published_documents(local_conn, remote).await?,
+        &remote_documents,
+    );
+
+    for (table, ids) in [("documents", &remove_documents), ("books", &remove_books)] {
+        has_modified =
+            delete_remote_rows_mysql(remote_conn, prefix, owner, table, ids).await? || has_modified;
+    }
+    if !upsert_books.is_empty() {
+        let mut batch = Batch::default();
+        for ids in upsert_books.chunks(ID_CHUNK_SIZE) {
+            let mut query_builder = QueryBuilder::<Sqlite>::new(
+                "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE id IN (",
+            );
+            let mut sep = query_builder.separated(", ");
+            for id in ids {
+                sep.push_bind(id);
+            }
+            sep.push_unseparated(") ");
+            let mut rows = query_builder.build_query_as::<Book>().fetch(local_conn);
+            while let Some(book) = rows.try_next().await? {
+                if let Some(books) = batch.push(book, 0) {
+                    upsert_remote_books_mysql(remote_conn, prefix, owner, books, true).await?;
+                }
+            }
         }
+        if let Some(books) = batch.finish() {
+            upsert_remote_books_mysql(remote_conn, prefix, owner, books, true).await?;
+        }
+        has_modified = true;
+    }
+    if !upsert_documents.is_empty() {
+        let mut batch = Batch::default();
+        for ids in upsert_documents.chunks(ID_CHUNK_SIZE) {
+            let mut query_builder = local_documents_query(ids);
+            let mut rows = query_builder.build_query_as::<Document>().fetch(local_conn);
+            while let Some(document) = rows.try_next().await? {
+                let document = render_document(document);
+                let size = document_size(&document);
+                if let Some(documents) = batch.push(document, size) {
+                    upsert_remote_documents_mysql(remote_conn, prefix, owner, documents).await?;
+                }
+            }
+        }
+        if let Some(documents) = batch.finish() {
+            upsert_remote_documents_mysql(remote_conn, prefix, owner, documents).await?;
+        }
+        has_modified = true;
     }
 
-    Ok(has_modified) // NOTE: return if changes were made
+    Ok(has_modified)
+}
+
+/// Compares the local library with the owner's rows without changing either, reporting every row
+/// that's missing from one side, differs, belongs to a missing book, or was deleted but is still
+/// there. Contents are hashed after they're decrypted, so it works on encrypted remotes too.
+pub async fn verify_remote_mysql(
+    local_conn: &Pool<Sqlite>,
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    key: Option<&LibraryKey>,
+) -> Result<Vec<Divergence>, SyncError> {
+    let shares: Vec<BookShare> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT owner, book, permission FROM book_shares WHERE grantee = ?",
+    ))
+    .bind(owner)
+    .fetch_all(remote_conn)
+    .await?;
+    let (shared_books, shared_documents) = local_shared_ids(local_conn, &shares).await?;
+    let local = local_inventory(local_conn, &shared_books, &shared_documents).await?;
+
+    let mut remote = Inventory::default();
+    let sql = prefix_tables(
+        prefix,
+        "SELECT id, name, modified, icon, icon_color, trash FROM books WHERE owner = ?",
+    );
+    let mut books = sqlx::query_as::<_, Book>(&sql)
+        .bind(owner)
+        .fetch(remote_conn);
+    while let Some(book) = books.try_next().await? {
+        remote.add_book(&decrypt_book(key, book)?);
+    }
+    let sql = prefix_tables(prefix, "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents WHERE owner = ?");
+    let mut documents = sqlx::query_as::<_, Document>(&sql)
+        .bind(owner)
+        .fetch(remote_conn);
+    while let Some(document) = documents.try_next().await? {
+        remote.add_document(&decrypt_document(key, document).and_then(decompress_document)?);
+    }
+    let sql = prefix_tables(prefix, "SELECT id FROM deleted WHERE owner = ?");
+    let mut deleted = sqlx::query_as::<_, Id>(&sql).bind(owner).fetch(remote_conn);
+    while let Some(id) = deleted.try_next().await? {
+        remote.add_deleted(id.id);
+    }
+    Ok(compare_inventories(&local, &remote))
+}
+
+/// Shares one of the owner's books with another user of the remote, or changes the permission
+/// it's shared with.
+pub async fn share_book_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    book: &str,
+    grantee: &str,
+    permission: &str,
+) -> Result<(), SyncError> {
+    let encrypted: Option<Id> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT owner AS id FROM key_check WHERE owner = ?",
+    ))
+    .bind(owner)
+    .fetch_optional(remote_conn)
+    .await?;
+    if encrypted.is_some() {
+        return Err("books can't be shared from an encrypted library"
+            .to_string()
+            .into());
+    }
+    let user: Option<Id> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT name AS id FROM users WHERE name = ?",
+    ))
+    .bind(grantee)
+    .fetch_optional(remote_conn)
+    .await?;
+    if user.is_none() {
+        return Err(format!("{grantee} hasn't synced with this remote yet").into());
+    }
+    let shared_book: Option<Id> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT id FROM books WHERE owner = ? AND id = ?",
+    ))
+    .bind(owner)
+    .bind(book)
+    .fetch_optional(remote_conn)
+    .await?;
+    if shared_book.is_none() {
+        return Err("sync the book with this remote before sharing it"
+            .to_string()
+            .into());
+    }
+    sqlx::query(&prefix_tables(prefix, "INSERT INTO book_shares (owner, book, grantee, permission) VALUES (?, ?, ?, ?) AS new ON DUPLICATE KEY UPDATE permission = new.permission"))
+        .bind(owner)
+        .bind(book)
+        .bind(grantee)
+        .bind(permission)
+        .execute(remote_conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn unshare_book_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    book: &str,
+    grantee: &str,
+) -> Result<(), SyncError> {
+    sqlx::query(&prefix_tables(
+        prefix,
+        "DELETE FROM book_shares WHERE owner = ? AND book = ? AND grantee = ?",
+    ))
+    .bind(owner)
+    .bind(book)
+    .bind(grantee)
+    .execute(remote_conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn book_grants_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    book: &str,
+) -> Result<Vec<Grant>, SyncError> {
+    sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT grantee, permission FROM book_shares WHERE owner = ? AND book = ? ORDER BY grantee",
+    ))
+    .bind(owner)
+    .bind(book)
+    .fetch_all(remote_conn)
+    .await
+    .map_err(SyncError::from)
+}
+
+/// Copies the owner's books and documents into a new snapshot named `name`, all at once.
+pub async fn create_snapshot_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    name: &str,
+) -> Result<(), SyncError> {
+    let mut tx = remote_conn.begin().await?;
+    let created = sqlx::query(&prefix_tables(
+        prefix,
+        "INSERT IGNORE INTO snapshots (owner, name, created) VALUES (?, ?, ?)",
+    ))
+    .bind(owner)
+    .bind(name)
+    .bind(Utc::now())
+    .execute(&mut *tx)
+    .await?;
+    if created.rows_affected() == 0 {
+        return Err(format!("there's already a snapshot named {name:?}").into());
+    }
+    sqlx::query(&prefix_tables(prefix, "INSERT INTO snapshot_books (owner, snapshot, id, name, modified, icon, icon_color, trash) SELECT owner, ?, id, name, modified, icon, icon_color, trash FROM books WHERE owner = ?"))
+        .bind(name)
+        .bind(owner)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(&prefix_tables(prefix, "INSERT INTO snapshot_documents (owner, snapshot, id, book, name, content, syntax, modified, icon, icon_color) SELECT owner, ?, id, book, name, content, syntax, modified, icon, icon_color FROM documents WHERE owner = ?"))
+        .bind(name)
+        .bind(owner)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The owner's snapshots, newest first.
+pub async fn snapshots_mysql(
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+) -> Result<Vec<Snapshot>, SyncError> {
+    sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT s.name, s.created, (SELECT count(*) FROM snapshot_books b WHERE b.owner = s.owner AND b.snapshot = s.name) AS books, (SELECT count(*) FROM snapshot_documents d WHERE d.owner = s.owner AND d.snapshot = s.name) AS documents FROM snapshots s WHERE s.owner = ? ORDER BY s.created DESC",
+    ))
+    .bind(owner)
+    .fetch_all(remote_conn)
+    .await
+    .map_err(SyncError::from)
+}
+
+/// Makes the library match the owner's snapshot named `name`. Its rows are restored as modified
+/// now, so the next sync pushes them everywhere.
+pub async fn restore_snapshot_mysql(
+    local_conn: &Pool<Sqlite>,
+    remote_conn: &Pool<MySql>,
+    prefix: &str,
+    owner: &str,
+    name: &str,
+    key: Option<&LibraryKey>,
+) -> Result<(), SyncError> {
+    let found: Option<ValueString> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT name AS value FROM snapshots WHERE owner = ? AND name = ?",
+    ))
+    .bind(owner)
+    .bind(name)
+    .fetch_optional(remote_conn)
+    .await?;
+    if found.is_none() {
+        return Err(format!("there's no snapshot named {name:?}").into());
+    }
+    let shares: Vec<BookShare> = sqlx::query_as(&prefix_tables(
+        prefix,
+        "SELECT owner, book, permission FROM book_shares WHERE grantee = ?",
+    ))
+    .bind(owner)
+    .fetch_all(remote_conn)
+    .await?;
+    let (shared_books, shared_documents) = local_shared_ids(local_conn, &shares).await?;
+    let mut books = vec![];
+    let books_query = prefix_tables(prefix, "SELECT id, name, modified, icon, icon_color, trash FROM snapshot_books WHERE owner = ? AND snapshot = ?");
+    let mut rows = sqlx::query_as::<_, Book>(&books_query)
+        .bind(owner)
+        .bind(name)
+        .fetch(remote_conn);
+    while let Some(book) = rows.try_next().await? {
+        books.push(decrypt_book(key, book)?);
+    }
+    let mut documents = vec![];
+    let documents_query = prefix_tables(prefix, "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM snapshot_documents WHERE owner = ? AND snapshot = ?");
+    let mut rows = sqlx::query_as::<_, Document>(&documents_query)
+        .bind(owner)
+        .bind(name)
+        .fetch(remote_conn);
+    while let Some(document) = rows.try_next().await? {
+        documents.push(decrypt_document(key, document).and_then(decompress_document)?);
+    }
+    restore_snapshot(
+        local_conn,
+        books,
+        documents,
+        &shared_books,
+        &shared_documents,
+    )
+    .await
 }
@@ -0,0 +1,114 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::SyncError;
+use futures_util::TryStreamExt;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashSet;
+
+// NOTE: Triggers log every insert, update and delete on the local books and documents in `changes`,
+// and each remote acknowledges the changes it was synced with in `change_acks`. Only the rows
+// changed since are pushed to it -- a remote that hasn't acknowledged anything yet (e.g. one just
+// added, or from before the outbox) compares every row instead.
+/// The local changes a remote hasn't acknowledged yet.
+pub struct Outbox {
+    /// The last change read, acknowledged once the sync succeeds.
+    pub seq: i64,
+    /// The changed rows, or `None` when every row has to be compared.
+    books: Option<HashSet<String>>,
+    documents: Option<HashSet<String>>,
+}
+
+impl Outbox {
+    pub fn book_changed(&self, id: &str) -> bool {
+        self.books.as_ref().is_none_or(|books| books.contains(id))
+    }
+
+    pub fn document_changed(&self, id: &str) -> bool {
+        self.documents
+            .as_ref()
+            .is_none_or(|documents| documents.contains(id))
+    }
+
+    /// The same outbox, but comparing every row.
+    pub fn everything(self) -> Self {
+        Outbox {
+            books: None,
+            documents: None,
+            ..self
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Change {
+    table_name: String,
+    row_id: String,
+}
+
+/// Reads the changes made since `remote` last acknowledged.
+pub async fn read_outbox(local_conn: &Pool<Sqlite>, remote: &str) -> Result<Outbox, SyncError> {
+    let seq: Option<i64> = sqlx::query_scalar("SELECT MAX(seq) FROM changes")
+        .fetch_one(local_conn)
+        .await?;
+    let seq = seq.unwrap_or_default();
+    let acknowledged: Option<i64> =
+        sqlx::query_scalar("SELECT seq FROM change_acks WHERE remote = ?")
+            .bind(remote)
+            .fetch_optional(local_conn)
+            .await?;
+    let Some(acknowledged) = acknowledged else {
+        return Ok(Outbox {
+            seq,
+            books: None,
+            documents: None,
+        });
+    };
+    let mut books = HashSet::new();
+    let mut documents = HashSet::new();
+    let mut changes = sqlx::query_as::<_, Change>(
+        "SELECT DISTINCT table_name, row_id FROM changes WHERE seq > ? AND seq <= ?",
+    )
+    .bind(acknowledged)
+    .bind(seq)
+    .fetch(local_conn);
+    while let Some(change) = changes.try_next().await? {
+        match change.table_name.as_str() {
+            "books" => books.insert(change.row_id),
+            _ => documents.insert(change.row_id),
+        };
+    }
+    Ok(Outbox {
+        seq,
+        books: Some(books),
+        documents: Some(documents),
+    })
+}
+
+/// Marks the changes up to `seq` as synced with `remote`, and drops the ones every remote has
+/// acknowledged.
+pub async fn acknowledge_outbox(
+    local_conn: &Pool<Sqlite>,
+    remote: &str,
+    seq: i64,
+) -> Result<(), SyncError> {
+    sqlx::query("INSERT INTO change_acks (remote, seq) VALUES (?, ?) ON CONFLICT(remote) DO UPDATE SET seq = EXCLUDED.seq")
+        .bind(remote)
+        .bind(seq)
+        .execute(local_conn)
+        .await?;
+    // NOTE: Remotes that haven't acknowledged anything compare every row anyway, so only the ones
+    // that have hold changes back
+    sqlx::query("DELETE FROM changes WHERE seq <= (SELECT MIN(seq) FROM change_acks)")
+        .execute(local_conn)
+        .await?;
+    Ok(())
+}
+
+/// Drops `remote`'s acknowledgement, so it compares every row on its next sync, and no longer holds
+/// back pruning changes.
+pub async fn forget_outbox(local_conn: &Pool<Sqlite>, remote: &str) -> Result<(), SyncError> {
+    sqlx::query("DELETE FROM change_acks WHERE remote = ?")
+        .bind(remote)
+        .execute(local_conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::SyncError;
+use crate::types::{Document, IdAndModified};
+use crate::{render_adoc, render_md};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use log::warn;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+
+// NOTE: A remote that's published to only gets the books picked for it in `published_books`, and
+// never changes the library. Books in the trash are left out, and nothing is encrypted or
+// compressed, since it's meant to be read by others -- each document's HTML is kept next to it.
+
+/// Checks a remote's publish setting before it's saved. Only MySQL and PostgreSQL remotes have
+/// somewhere to keep rendered documents.
+pub fn check_publish(db_type: &str, publish: bool) -> Result<(), String> {
+    match db_type {
+        "mysql" | "postgresql" => Ok(()),
+        _ if !publish => Ok(()),
+        db_type => Err(format!("{db_type:?} remotes can't be published to")),
+    }
+}
+
+/// The books published to `remote` that aren't in the trash, with when they were modified.
+pub async fn published_books(
+    local_conn: &Pool<Sqlite>,
+    remote: &str,
+) -> Result<HashMap<String, DateTime<Utc>>, SyncError> {
+    sqlx::query_as::<_, IdAndModified>(
+        "SELECT b.id, b.modified FROM books b JOIN published_books p ON p.book = b.id WHERE p.remote = ? AND b.trash = 0",
+    )
+    .bind(remote)
+    .fetch(local_conn)
+    .map_ok(|kv| (kv.id, kv.modified))
+    .try_collect()
+    .await
+    .map_err(SyncError::from)
+}
+
+/// The documents in the books published to `remote`, with when they were modified.
+pub async fn published_documents(
+    local_conn: &Pool<Sqlite>,
+    remote: &str,
+) -> Result<HashMap<String, DateTime<Utc>>, SyncError> {
+    sqlx::query_as::<_, IdAndModified>(
+        "SELECT d.id, d.modified FROM documents d JOIN books b ON b.id = d.book JOIN published_books p ON p.book = b.id WHERE p.remote = ? AND b.trash = 0",
+    )
+    .bind(remote)
+    .fetch(local_conn)
+    .map_ok(|kv| (kv.id, kv.modified))
+    .try_collect()
+    .await
+    .map_err(SyncError::from)
+}
+
+/// Compares the published rows with the remote's, returning the remote rows to remove and the
+/// local rows to upsert. The local side always wins, so any difference is upserted.
+pub fn publish_diff(
+    local: &HashMap<String, DateTime<Utc>>,
+    remote: &HashMap<String, DateTime<Utc>>,
+) -> (Vec<String>, Vec<String>) {
+    let remove = remote
+        .keys()
+        .filter(|id| !local.contains_key(*id))
+        .cloned()
+        .collect();
+    // NOTE: Compared to the second, which is as precise as every remote keeps them
+    let upsert = local
+        .iter()
+        .filter(|(id, modified)| {
+            remote.get(*id).map(|m| m.timestamp()) != Some(modified.timestamp())
+        })
+        .map(|(id, _)| id.clone())
+        .collect();
+    (remove, upsert)
+}
+
+/// Renders a document's content to HTML for a simple viewer. HTML documents are kept as they are,
+/// and ones that fail to render are published without it.
+pub fn render_document(document: Document) -> Document {
+    let rendered = match (document.syntax.as_str(), &document.content) {
+        (_, None) => None,
+        ("md", Some(content)) => Some(render_md(content)),
+        ("adoc", Some(content)) => match render_adoc(content) {
+            Ok(html) => Some(html),
+            Err(e) => {
+                warn!("couldn't render {} to publish it: {e}", document.id);
+                None
+            }
+        },
+        (_, Some(content)) => Some(content.clone()),
+    };
+    Document {
+        rendered,
+        ..document
+    }
+}
@@ -0,0 +1,504 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::ScriptoriumError;
+use sqlx::FromRow;
+
+/// A column as the migrations leave it: `(table, column, data type, nullable)`, with the data type
+/// as `information_schema.columns` names it.
+type ExpectedColumn = (&'static str, &'static str, &'static str, bool);
+
+pub const MYSQL_SCHEMA: [ExpectedColumn; 54] = [
+    ("books", "id", "varchar", false),
+    ("books", "owner", "varchar", false),
+    ("books", "name", "text", true),
+    ("books", "modified", "datetime", false),
+    ("books", "icon", "text", true),
+    ("books", "icon_color", "text", true),
+    ("books", "trash", "int", false),
+    ("books", "published", "tinyint", false),
+    ("documents", "id", "varchar", false),
+    ("documents", "owner", "varchar", false),
+    ("documents", "book", "varchar", false),
+    ("documents", "name", "text", true),
+    ("documents", "content", "text", true),
+    ("documents", "syntax", "text", false),
+    ("documents", "modified", "datetime", false),
+    ("documents", "icon", "text", true),
+    ("documents", "icon_color", "text", true),
+    ("documents", "content_hash", "varchar", true),
+    ("documents", "rendered", "mediumtext", true),
+    ("deleted", "id", "varchar", false),
+    ("deleted", "owner", "varchar", false),
+    ("key_check", "id", "int", false),
+    ("key_check", "owner", "varchar", false),
+    ("key_check", "salt", "text", false),
+    ("key_check", "check_value", "text", false),
+    ("users", "name", "varchar", false),
+    ("users", "created", "datetime", false),
+    ("book_shares", "owner", "varchar", false),
+    ("book_shares", "book", "varchar", false),
+    ("book_shares", "grantee", "varchar", false),
+    ("book_shares", "permission", "varchar", false),
+    ("metadata", "name", "varchar", false),
+    ("metadata", "value", "text", false),
+    ("snapshots", "owner", "varchar", false),
+    ("snapshots", "name", "varchar", false),
+    ("snapshots", "created", "datetime", false),
+    ("snapshot_books", "owner", "varchar", false),
+    ("snapshot_books", "snapshot", "varchar", false),
+    ("snapshot_books", "id", "varchar", false),
+    ("snapshot_books", "name", "text", true),
+    ("snapshot_books", "modified", "datetime", false),
+    ("snapshot_books", "icon", "text", true),
+    ("snapshot_books", "icon_color", "text", true),
+    ("snapshot_books", "trash", "int", false),
+    ("snapshot_documents", "owner", "varchar", false),
+    ("snapshot_documents", "snapshot", "varchar", false),
+    ("snapshot_documents", "id", "varchar", false),
+    ("snapshot_documents", "book", "varchar", false),
+    ("snapshot_documents", "name", "text", true),
+    ("snapshot_documents", "content", "text", true),
+    ("snapshot_documents", "syntax", "text", false),
+    ("snapshot_documents", "modified", "datetime", false),
+    ("snapshot_documents", "icon", "text", true),
+    ("snapshot_documents", "icon_color", "text", true),
+];
+
+pub const PG_SCHEMA: [ExpectedColumn; 55] = [
+    ("books", "id", "character varying", false),
+    ("books", "owner", "character varying", false),
+    ("books", "name", "text", true),
+    ("books", "modified", "timestamp with time zone", false),
+    ("books", "icon", "text", true),
+    ("books", "icon_color", "text", true),
+    ("books", "trash", "integer", false),
+    ("books", "published", "boolean", false),
+    ("documents", "id", "character varying", false),
+    ("documents", "owner", "character varying", false),
+    ("documents", "book", "character varying", false),
+    ("documents", "name", "text", true),
+    ("documents", "content", "text", true),
+    ("documents", "syntax", "text", false),
+    ("documents", "modified", "timestamp with time zone", false),
+    ("documents", "icon", "text", true),
+    ("documents", "icon_color", "text", true),
+    ("documents", "content_hash", "character varying", true),
+    ("documents", "rendered", "text", true),
+    ("deleted", "id", "character varying", false),
+    ("deleted", "owner", "character varying", false),
+    ("deleted", "book", "character varying", true),
+    ("key_check", "id", "integer", false),
+    ("key_check", "owner", "character varying", false),
+    ("key_check", "salt", "text", false),
+    ("key_check", "check_value", "text", false),
+    ("users", "name", "character varying", false),
+    ("users", "created", "timestamp with time zone", false),
+    ("book_shares", "owner", "character varying", false),
+    ("book_shares", "book", "character varying", false),
+    ("book_shares", "grantee", "character varying", false),
+    ("book_shares", "permission", "character varying", false),
+    ("metadata", "name", "character varying", false),
+    ("metadata", "value", "text", false),
+    ("snapshots", "owner", "character varying", false),
+    ("snapshots", "name", "character varying", false),
+    ("snapshots", "created", "timestamp with time zone", false),
+    ("snapshot_books", "owner", "character varying", false),
+    ("snapshot_books", "snapshot", "character varying", false),
+    ("snapshot_books", "id", "character varying", false),
+    ("snapshot_books", "name", "text", true),
+    (
+        "snapshot_books",
+        "modified",
+        "timestamp with time zone",
+        false,
+    ),
+    ("snapshot_books", "icon", "text", true),
+    ("snapshot_books", "icon_color", "text", true),
+    ("snapshot_books", "trash", "integer", false),
+    ("snapshot_documents", "owner", "character varying", false),
+    ("snapshot_documents", "snapshot", "character varying", false),
+    ("snapshot_documents", "id", "character varying", false),
+    ("snapshot_documents", "book", "character varying", false),
+    ("snapshot_documents", "name", "text", true),
+    ("snapshot_documents", "content", "text", true),
+    ("snapshot_documents", "syntax", "text", false),
+    (
+        "snapshot_documents",
+        "modified",
+        "timestamp with time zone",
+        false,
+    ),
+    ("snapshot_documents", "icon", "text", true),
+    ("snapshot_documents", "icon_color", "text", true),
+];
+
+// NOTE: Every table the remote migrations create, which is what a MySQL remote's table prefix is
+// put in front of
+const REMOTE_TABLES: [&str; 10] = [
+    "books",
+    "documents",
+    "deleted",
+    "key_check",
+    "users",
+    "book_shares",
+    "metadata",
+    "snapshots",
+    "snapshot_books",
+    "snapshot_documents",
+];
+
+fn push_word(prefixed: &mut String, word: &mut String, prefix: &str) {
+    if REMOTE_TABLES.contains(&word.as_str()) {
+        prefixed.push_str(prefix);
+    }
+    prefixed.push_str(word);
+    word.clear();
+}
+
+/// Puts a MySQL remote's table prefix in front of each remote table named in a statement. Quoted
+/// strings are left alone, unless they're just a table name -- as when looking one up in
+/// `information_schema`.
+pub fn prefix_tables(prefix: &str, sql: &str) -> String {
+    if prefix.is_empty() {
+        return sql.to_string();
+    }
+    let mut prefixed = String::with_capacity(sql.len());
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    for c in sql.chars() {
+        match quote {
+            Some(q) if q == c => {
+                push_word(&mut prefixed, &mut word, prefix);
+                quote = None;
+            }
+            Some(_) => {
+                word.push(c);
+                continue;
+            }
+            None if c.is_ascii_alphanumeric() || c == '_' => {
+                word.push(c);
+                continue;
+            }
+            None => {
+                push_word(&mut prefixed, &mut word, prefix);
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+            }
+        }
+        prefixed.push(c);
+    }
+    if quote.is_some() {
+        prefixed.push_str(&word);
+    } else {
+        push_word(&mut prefixed, &mut word, prefix);
+    }
+    prefixed
+}
+
+#[derive(FromRow, Debug, Clone)]
+pub struct ColumnInfo {
+    pub table_name: String,
+    pub column_name: String,
+    pub data_type: String,
+    pub is_nullable: String,
+}
+
+/// Describes each way the remote's columns differ from the expected ones. Extra columns are left
+/// alone, since they don't get in the way of syncing.
+pub fn schema_drift(expected: &[ExpectedColumn], actual: &[ColumnInfo]) -> Vec<String> {
+    let mut drift = vec![];
+    for (table, column, data_type, nullable) in expected {
+        let found = actual
+            .iter()
+            .find(|c| c.table_name == *table && c.column_name == *column);
+        match found {
+            None => drift.push(format!("{table}.{column} is missing")),
+            Some(found) => {
+                if !found.data_type.eq_ignore_ascii_case(data_type) {
+                    drift.push(format!(
+                        "{table}.{column} is {}, expected {data_type}",
+                        found.data_type
+                    ));
+                }
+                let found_nullable = found.is_nullable.eq_ignore_ascii_case("YES");
+                if found_nullable != *nullable {
+                    drift.push(format!(
+                        "{table}.{column} is {}, expected {}",
+                        if found_nullable {
+                            "nullable"
+                        } else {
+                            "NOT NULL"
+                        },
+                        if *nullable { "nullable" } else { "NOT NULL" },
+                    ));
+                }
+            }
+        }
+    }
+    drift
+}
+
+pub fn drift_error(drift: Vec<String>) -> Result<(), ScriptoriumError> {
+    if drift.is_empty() {
+        Ok(())
+    } else {
+        Err(ScriptoriumError::SchemaMismatch {
+            message: format!(
+                "the remote's schema doesn't match what Scriptorium expects: {}",
+                drift.join("; ")
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::{MYSQL_MIGRATIONS, PG_MIGRATIONS};
+    use tauri_plugin_sql::Migration;
+
+    const PREFIX: &str = "pfx_";
+
+    fn words(sql: &str) -> impl Iterator<Item = &str> {
+        sql.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+    }
+
+    #[test]
+    fn every_migration_is_prefixed() {
+        for migration in &MYSQL_MIGRATIONS.0 {
+            let prefixed = prefix_tables(PREFIX, migration.sql);
+            // NOTE: Only ever put in front of a name, never in the middle of one
+            assert_eq!(prefixed.replace(PREFIX, ""), migration.sql);
+            for word in words(&prefixed) {
+                assert!(
+                    !REMOTE_TABLES.contains(&word),
+                    "{word} isn't prefixed in migration {}",
+                    migration.version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn foreign_key_lookup() {
+        let owners = MYSQL_MIGRATIONS
+            .0
+            .iter()
+            .find(|m| m.description == "owners")
+            .unwrap();
+        let prefixed = prefix_tables(PREFIX, owners.sql);
+        assert!(prefixed.contains("TABLE_NAME = 'pfx_documents'"));
+        assert!(prefixed.contains("REFERENCED_TABLE_NAME = 'pfx_books'"));
+        assert!(prefixed.contains("'ALTER TABLE `', 'pfx_documents', '` DROP FOREIGN KEY `'"));
+        assert!(!prefixed.contains("ibfk"));
+    }
+
+    #[test]
+    fn names_containing_tables() {
+        assert_eq!(
+            prefix_tables(
+                PREFIX,
+                "SELECT published_books, b.documents_count FROM snapshot_documents JOIN books b"
+            ),
+            "SELECT published_books, b.documents_count FROM pfx_snapshot_documents JOIN pfx_books b"
+        );
+        assert_eq!(
+            prefix_tables(
+                PREFIX,
+                "SELECT 'books', 'the books', \"documents\" FROM metadata WHERE name = 'it''s'"
+            ),
+            "SELECT 'pfx_books', 'the books', \"pfx_documents\" FROM pfx_metadata WHERE name = 'it''s'"
+        );
+        assert_eq!(prefix_tables(PREFIX, "SELECT 'books"), "SELECT 'books");
+        assert_eq!(
+            prefix_tables("", "SELECT * FROM books"),
+            "SELECT * FROM books"
+        );
+    }
+
+    // NOTE: The data types the migrations use, as `information_schema.columns` names them
+    const MYSQL_TYPES: [(&str, &str); 7] = [
+        ("VARCHAR", "varchar"),
+        ("TEXT", "text"),
+        ("MEDIUMTEXT", "mediumtext"),
+        ("TIMESTAMP", "timestamp"),
+        ("DATETIME", "datetime"),
+        ("INTEGER", "int"),
+        ("BOOLEAN", "tinyint"),
+    ];
+    const PG_TYPES: [(&str, &str); 6] = [
+        ("VARCHAR", "character varying"),
+        ("TEXT", "text"),
+        ("TIMESTAMP", "timestamp without time zone"),
+        ("TIMESTAMPTZ", "timestamp with time zone"),
+        ("INTEGER", "integer"),
+        ("BOOLEAN", "boolean"),
+    ];
+
+    fn column_info(table: &str, column: &str, data_type: &str, nullable: bool) -> ColumnInfo {
+        ColumnInfo {
+            table_name: table.to_string(),
+            column_name: column.to_string(),
+            data_type: data_type.to_string(),
+            is_nullable: if nullable { "YES" } else { "NO" }.to_string(),
+        }
+    }
+
+    fn data_type(types: &[(&str, &'static str)], declared: &str) -> &'static str {
+        let declared = declared.split('(').next().unwrap();
+        types
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(declared))
+            .unwrap_or_else(|| panic!("unknown data type {declared}"))
+            .1
+    }
+
+    /// A column defined as e.g. `name VARCHAR(255) NOT NULL`.
+    fn define(table: &str, definition: &str, types: &[(&str, &'static str)]) -> ColumnInfo {
+        let words: Vec<&str> = definition.split_whitespace().collect();
+        let upper = definition.to_uppercase();
+        let nullable = !upper.contains("NOT NULL") && !upper.contains("PRIMARY KEY");
+        column_info(table, words[0], data_type(types, words[1]), nullable)
+    }
+
+    /// Splits a table's definitions on the commas outside parentheses.
+    fn definitions(body: &str) -> Vec<&str> {
+        let (mut depth, mut start, mut parts) = (0, 0, vec![]);
+        for (i, c) in body.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(body[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(body[start..].trim());
+        parts
+    }
+
+    /// Plays the migrations' `CREATE TABLE`s and `ALTER TABLE`s, to find the columns they leave.
+    fn migrated_columns(
+        migrations: &[Migration],
+        types: &[(&str, &'static str)],
+    ) -> Vec<ColumnInfo> {
+        let mut columns: Vec<ColumnInfo> = vec![];
+        for statement in migrations.iter().flat_map(|m| m.sql.split(';')) {
+            let statement = statement.trim();
+            let words: Vec<&str> = statement.split_whitespace().collect();
+            match words.as_slice() {
+                ["CREATE", "TABLE", "IF", "NOT", "EXISTS", table, ..] => {
+                    let body =
+                        &statement[statement.find('(').unwrap() + 1..statement.rfind(')').unwrap()];
+                    for definition in definitions(body) {
+                        match definition.split_whitespace().next().unwrap() {
+                            "PRIMARY" => {
+                                let keys = &definition[definition.find('(').unwrap() + 1
+                                    ..definition.find(')').unwrap()];
+                                for key in keys.split(',') {
+                                    let column = columns
+                                        .iter_mut()
+                                        .find(|c| {
+                                            c.table_name == *table && c.column_name == key.trim()
+                                        })
+                                        .unwrap();
+                                    column.is_nullable = "NO".to_string();
+                                }
+                            }
+                            "FOREIGN" | "CHECK" | "UNIQUE" | "CONSTRAINT" => {}
+                            _ => columns.push(define(table, definition, types)),
+                        }
+                    }
+                }
+                ["ALTER", "TABLE", table, "ADD", "COLUMN", ..] => {
+                    columns.push(define(table, &words[5..].join(" "), types));
+                }
+                ["ALTER", "TABLE", table, "MODIFY", column, ..] => {
+                    let defined = define(table, &words[4..].join(" "), types);
+                    let found = columns
+                        .iter_mut()
+                        .find(|c| c.table_name == *table && c.column_name == *column)
+                        .unwrap();
+                    *found = defined;
+                }
+                ["ALTER", "TABLE", table, "ALTER", "COLUMN", column, "TYPE", declared] => {
+                    let found = columns
+                        .iter_mut()
+                        .find(|c| c.table_name == *table && c.column_name == *column)
+                        .unwrap();
+                    found.data_type = data_type(types, declared).to_string();
+                }
+                _ => {}
+            }
+        }
+        columns
+    }
+
+    fn assert_matches_migrations(expected: &[ExpectedColumn], columns: Vec<ColumnInfo>) {
+        assert_eq!(schema_drift(expected, &columns), Vec::<String>::new());
+        for column in &columns {
+            assert!(
+                expected
+                    .iter()
+                    .any(|(table, name, ..)| *table == column.table_name
+                        && *name == column.column_name),
+                "{}.{} isn't expected",
+                column.table_name,
+                column.column_name
+            );
+        }
+    }
+
+    #[test]
+    fn mysql_schema_matches_migrations() {
+        assert_matches_migrations(
+            &MYSQL_SCHEMA,
+            migrated_columns(&MYSQL_MIGRATIONS.0, &MYSQL_TYPES),
+        );
+    }
+
+    #[test]
+    fn pg_schema_matches_migrations() {
+        assert_matches_migrations(&PG_SCHEMA, migrated_columns(&PG_MIGRATIONS.0, &PG_TYPES));
+    }
+
+    #[test]
+    fn drift() {
+        let expected = [
+            ("books", "id", "varchar", false),
+            ("books", "name", "text", true),
+            ("books", "modified", "datetime", false),
+        ];
+        let actual = vec![
+            column_info("books", "id", "VARCHAR", false),
+            column_info("books", "name", "text", false),
+            column_info("books", "modified", "timestamp", false),
+            column_info("books", "extra", "text", true),
+        ];
+        assert_eq!(
+            schema_drift(&expected, &actual),
+            vec![
+                "books.name is NOT NULL, expected nullable",
+                "books.modified is timestamp, expected datetime",
+            ]
+        );
+        assert_eq!(
+            schema_drift(&expected, &actual[..1]),
+            vec!["books.name is missing", "books.modified is missing"]
+        );
+
+        assert!(drift_error(vec![]).is_ok());
+        match drift_error(vec![
+            "books.name is missing".to_string(),
+            "books.id is text, expected varchar".to_string(),
+        ]) {
+            Err(ScriptoriumError::SchemaMismatch { message }) => assert!(
+                message.ends_with("books.name is missing; books.id is text, expected varchar"),
+                "{message}"
+            ),
+            e => panic!("expected a schema mismatch, got {e:?}"),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::SyncError;
+use crate::types::{Book, BookShare, Document, Id, IdAndModified, IdModifiedAndHash};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::{Executor, Pool, QueryBuilder, Sqlite};
+use std::collections::{HashMap, HashSet};
+
+pub const SHARE_PERMISSIONS: [&str; 2] = ["read", "write"];
+
+/// Checks a share before it's granted. Every library has its own `default` and `trash` books, so
+/// they can't be shared.
+pub fn check_share(owner: &str, book: &str, grantee: &str, permission: &str) -> Result<(), String> {
+    if book == "default" || book == "trash" {
+        return Err(format!("the {book} book can't be shared"));
+    }
+    if grantee == owner {
+        return Err("books can't be shared with their owner".to_string());
+    }
+    if !SHARE_PERMISSIONS.contains(&permission) {
+        return Err(format!("Unrecognized share permission: {permission:?}"));
+    }
+    Ok(())
+}
+
+/// The ids of the local books shared with this user, and of their documents. They're synced with
+/// their owners' rows, so they're left out of this user's own.
+pub async fn local_shared_ids(
+    local_conn: &Pool<Sqlite>,
+    shares: &[BookShare],
+) -> Result<(HashSet<String>, HashSet<String>), SyncError> {
+    let books: HashSet<String> = shares.iter().map(|s| s.book.clone()).collect();
+    if books.is_empty() {
+        return Ok((books, HashSet::new()));
+    }
+    let mut query_builder = QueryBuilder::<Sqlite>::new("SELECT id FROM documents WHERE book IN (");
+    let mut sep = query_builder.separated(", ");
+    for book in &books {
+        sep.push_bind(book);
+    }
+    sep.push_unseparated(")");
+    let documents: HashSet<String> = query_builder
+        .build_query_as::<Id>()
+        .fetch(local_conn)
+        .map_ok(|d| d.id)
+        .try_collect()
+        .await?;
+    Ok((books, documents))
+}
+
+pub async fn local_deletions<'e>(
+    local_conn: impl Executor<'e, Database = Sqlite>,
+) -> Result<HashSet<String>, SyncError> {
+    sqlx::query_as::<_, Id>("SELECT id FROM deleted")
+        .fetch(local_conn)
+        .map_ok(|d| d.id)
+        .try_collect()
+        .await
+        .map_err(SyncError::from)
+}
+
+pub async fn local_book_modified(
+    local_conn: &Pool<Sqlite>,
+    book: &str,
+) -> Result<Option<DateTime<Utc>>, SyncError> {
+    let modified: Option<IdAndModified> =
+        sqlx::query_as("SELECT id, modified FROM books WHERE id = ?")
+            .bind(book)
+            .fetch_optional(local_conn)
+            .await?;
+    Ok(modified.map(|m| m.modified))
+}
+
+pub async fn local_documents_modified(
+    local_conn: &Pool<Sqlite>,
+    book: &str,
+) -> Result<HashMap<String, IdModifiedAndHash>, SyncError> {
+    sqlx::query_as::<_, IdModifiedAndHash>(
+        "SELECT id, modified, content_hash FROM documents WHERE book = ?",
+    )
+    .bind(book)
+    .fetch(local_conn)
+    .map_ok(|kv| (kv.id.clone(), kv))
+    .try_collect()
+    .await
+    .map_err(SyncError::from)
+}
+
+pub async fn local_book(local_conn: &Pool<Sqlite>, book: &str) -> Result<Book, SyncError> {
+    sqlx::query_as("SELECT id, name, modified, icon, icon_color, trash FROM books WHERE id = ?")
+        .bind(book)
+        .fetch_one(local_conn)
+        .await
+        .map_err(SyncError::from)
+}
+
+/// Selects the local documents with these ids, to be streamed to a remote.
+pub fn local_documents_query(ids: &[String]) -> QueryBuilder<'_, Sqlite> {
+    let mut query_builder = QueryBuilder::<Sqlite>::new(
+        "SELECT id, book, name, modified, content, syntax, icon, icon_color, content_hash FROM documents WHERE id IN (",
+    );
+    let mut sep = query_builder.separated(", ");
+    for id in ids {
+        sep.push_bind(id);
+    }
+    sep.push_unseparated(")");
+    query_builder
+}
+
+pub async fn upsert_local_books<'e>(
+    local_conn: impl Executor<'e, Database = Sqlite>,
+    books: Vec<Book>,
+) -> Result<(), SyncError> {
+    let mut query_builder = QueryBuilder::<Sqlite>::new(
+        "INSERT INTO books (id, name, modified, icon, icon_color, trash) ",
+    );
+    query_builder.push_values(books, |mut sep, row| {
+        sep.push_bind(row.id)
+            .push_bind(row.name)
+            .push_bind(row.modified)
+            .push_bind(row.icon)
+            .push_bind(row.icon_color)
+            .push_bind(row.trash);
+    });
+    query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, modified = EXCLUDED.modified, icon = EXCLUDED.icon, icon_color = EXCLUDED.icon_color, trash = EXCLUDED.trash");
+    query_builder.build().execute(local_conn).await?;
+    Ok(())
+}
+
+pub async fn upsert_local_documents<'e>(
+    local_conn: impl Executor<'e, Database = Sqlite>,
+    documents: Vec<Document>,
+) -> Result<(), SyncError> {
+    let mut query_builder = QueryBuilder::<Sqlite>::new(
+        "INSERT INTO documents (id, book, name, modified, content, syntax, icon, icon_color) ",
+    );
+    query_builder.push_values(documents, |mut sep, row| {
+        sep.push_bind(row.id)
+            .push_bind(row.book)
+            .push_bind(row.name)
+            .push_bind(row.modified)
+            .push_bind(row.content)
+            .push_bind(row.syntax)
+            .push_bind(row.icon)
+            .push_bind(row.icon_color);
+    });
+    query_builder.push(" ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, book = EXCLUDED.book, modified = EXCLUDED.modified, content = EXCLUDED.content, syntax = EXCLUDED.syntax, icon = EXCLUDED.icon, icon_color = EXCLUDED.icon_color");
+    query_builder.build().execute(local_conn).await?;
+    Ok(())
+}
+
+/// Deletes local documents that were deleted remotely -- the local `deleted` table is filled in by
+/// its trigger, so they aren't pulled again.
+pub async fn delete_local_documents(
+    local_conn: &Pool<Sqlite>,
+    ids: &[String],
+) -> Result<(), SyncError> {
+    let mut query_builder = QueryBuilder::<Sqlite>::new("DELETE FROM documents WHERE id IN (");
+    let mut sep = query_builder.separated(", ");
+    for id in ids {
+        sep.push_bind(id);
+    }
+    sep.push_unseparated(")");
+    query_builder.build().execute(local_conn).await?;
+    Ok(())
+}
+
+pub fn shared_error(share: &BookShare, e: String) -> String {
+    format!(
+        "couldn't read the book {} shared by {}: {e}",
+        share.book, share.owner
+    )
+}
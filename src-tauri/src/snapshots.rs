@@ -0,0 +1,180 @@
+// Copyright (C) 2025  Athan Clark
+use crate::batches::{document_size, Batch, ID_CHUNK_SIZE};
+use crate::error::SyncError;
+use crate::shares::{local_deletions, upsert_local_books, upsert_local_documents};
+use crate::types::{Book, Document, Id, RemoteServer};
+use chrono::{DateTime, TimeDelta, Utc};
+use futures_util::TryStreamExt;
+use sqlx::{Pool, QueryBuilder, Sqlite, Transaction};
+use std::collections::{HashMap, HashSet};
+
+// NOTE: A snapshot copies the owner's books and documents into `snapshot_books` and
+// `snapshot_documents` on the remote, without leaving it -- so they stay encrypted and compressed
+// if they were. It's taken of what the remote has, which is the library as of its last sync.
+
+/// The longest name a snapshot can have, as long as the remote's column.
+const SNAPSHOT_NAME_LENGTH: usize = 255;
+
+/// Checks that a remote can keep snapshots. Git remotes have their history instead, and remotes
+/// that are published to only have the books picked for them.
+pub fn check_snapshot_remote(remote: &RemoteServer) -> Result<(), String> {
+    match remote.db_type.as_str() {
+        "git" => Err("git remotes keep their history instead of snapshots".to_string()),
+        _ if remote.publish => {
+            Err("remotes that are published to don't have the whole library".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn check_snapshot_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("snapshots need a name".to_string());
+    }
+    if name.chars().count() > SNAPSHOT_NAME_LENGTH {
+        return Err(format!(
+            "snapshot names can't be longer than {SNAPSHOT_NAME_LENGTH} characters"
+        ));
+    }
+    Ok(())
+}
+
+/// How a snapshot's rows are put back in the library.
+struct Restore {
+    /// New ids for the rows that were permanently deleted since -- their deletions have already
+    /// been synced, so they'd be deleted again under their old ones.
+    renamed: HashMap<String, String>,
+    /// When the rows are restored -- after anything in the library, so they're newer than every
+    /// remote's copy it's been synced with, even from a clock that's ahead.
+    modified: DateTime<Utc>,
+}
+
+impl Restore {
+    fn rename(&self, id: String) -> String {
+        self.renamed.get(&id).cloned().unwrap_or(id)
+    }
+
+    fn book(&self, book: Book) -> Book {
+        Book {
+            id: self.rename(book.id),
+            modified: self.modified,
+            ..book
+        }
+    }
+
+    fn document(&self, document: Document) -> Document {
+        Document {
+            id: self.rename(document.id),
+            book: self.rename(document.book),
+            modified: self.modified,
+            ..document
+        }
+    }
+}
+
+// NOTE: The delete triggers add their tombstones, so they're deleted on the remotes too
+async fn delete_local_rows(
+    tx: &mut Transaction<'_, Sqlite>,
+    table: &str,
+    ids: &[String],
+) -> Result<(), SyncError> {
+    for ids in ids.chunks(ID_CHUNK_SIZE) {
+        let mut query_builder =
+            QueryBuilder::<Sqlite>::new(format!("DELETE FROM {table} WHERE id IN ("));
+        let mut sep = query_builder.separated(", ");
+        for id in ids {
+            sep.push_bind(id);
+        }
+        sep.push_unseparated(")");
+        query_builder.build().execute(&mut **tx).await?;
+    }
+    Ok(())
+}
+
+async fn local_ids(
+    tx: &mut Transaction<'_, Sqlite>,
+    table: &str,
+) -> Result<Vec<String>, SyncError> {
+    sqlx::query_as::<_, Id>(&format!("SELECT id FROM {table}"))
+        .fetch(&mut **tx)
+        .map_ok(|kv| kv.id)
+        .try_collect()
+        .await
+        .map_err(SyncError::from)
+}
+
+/// Makes the library match a snapshot's books and documents, already read and decrypted, all at
+/// once -- so a restore that fails part way leaves the library as it was. Books shared with this
+/// user aren't in it, so they're kept, as are the `default` and `trash` books every library has.
+pub async fn restore_snapshot(
+    local_conn: &Pool<Sqlite>,
+    books: Vec<Book>,
+    documents: Vec<Document>,
+    shared_books: &HashSet<String>,
+    shared_documents: &HashSet<String>,
+) -> Result<(), SyncError> {
+    let mut tx = local_conn.begin().await?;
+    let newest: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT MAX(modified) FROM (SELECT modified FROM books UNION ALL SELECT modified FROM documents)",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let modified = newest.map_or(Utc::now(), |newest| {
+        Utc::now().max(newest + TimeDelta::seconds(1))
+    });
+    let deleted = local_deletions(&mut *tx).await?;
+    let book_ids: HashSet<String> = books.iter().map(|book| book.id.clone()).collect();
+    let document_ids: HashSet<String> = documents.iter().map(|doc| doc.id.clone()).collect();
+    let mut renamed = HashMap::new();
+    for id in book_ids
+        .iter()
+        .chain(&document_ids)
+        .filter(|id| deleted.contains(*id))
+    {
+        let new_id: String = sqlx::query_scalar("SELECT lower(hex(randomblob(16)))")
+            .fetch_one(&mut *tx)
+            .await?;
+        renamed.insert(id.clone(), new_id);
+    }
+    let restore = Restore { renamed, modified };
+
+    let stale_documents: Vec<String> = local_ids(&mut tx, "documents")
+        .await?
+        .into_iter()
+        .filter(|id| !document_ids.contains(id) && !shared_documents.contains(id))
+        .collect();
+    // NOTE: The books are deleted last, since documents the snapshot has may have been moved into
+    // them
+    let stale_books: Vec<String> = local_ids(&mut tx, "books")
+        .await?
+        .into_iter()
+        .filter(|id| {
+            !book_ids.contains(id) && !shared_books.contains(id) && id != "default" && id != "trash"
+        })
+        .collect();
+    delete_local_rows(&mut tx, "documents", &stale_documents).await?;
+
+    let mut batch = Batch::default();
+    for book in books {
+        if let Some(books) = batch.push(restore.book(book), 0) {
+            upsert_local_books(&mut *tx, books).await?;
+        }
+    }
+    if let Some(books) = batch.finish() {
+        upsert_local_books(&mut *tx, books).await?;
+    }
+    let mut batch = Batch::default();
+    for document in documents {
+        let document = restore.document(document);
+        let size = document_size(&document);
+        if let Some(documents) = batch.push(document, size) {
+            upsert_local_documents(&mut *tx, documents).await?;
+        }
+    }
+    if let Some(documents) = batch.finish() {
+        upsert_local_documents(&mut *tx, documents).await?;
+    }
+    delete_local_rows(&mut tx, "books", &stale_books).await?;
+    tx.commit().await?;
+    Ok(())
+}
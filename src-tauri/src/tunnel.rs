@@ -0,0 +1,203 @@
+// Copyright (C) 2025  Athan Clark
+use crate::proxy::Proxy;
+use crate::types::RemoteServer;
+use log::{debug, warn};
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{sleep, spawn},
+    time::{Duration, Instant},
+};
+
+const TUNNEL_TIMEOUT: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Forward {
+    Ssh(Child),
+    // NOTE: Set to stop accepting connections
+    Proxy(Arc<AtomicBool>),
+}
+
+/// A local port forwarded to the remote database, either by `ssh` or through a proxy. The forward
+/// is closed when this is dropped, so it has to outlive the pool connected through it.
+pub struct Tunnel {
+    forward: Forward,
+    port: u16,
+}
+
+impl Tunnel {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn is_ssh(&self) -> bool {
+        matches!(self.forward, Forward::Ssh(_))
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        match &mut self.forward {
+            Forward::Ssh(child) => {
+                if let Err(e) = child.kill() {
+                    warn!("couldn't stop ssh tunnel: {e}");
+                }
+                let _ = child.wait();
+            }
+            Forward::Proxy(stopped) => stopped.store(true, Ordering::Relaxed),
+        }
+        debug!("tunnel on port {} closed", self.port);
+    }
+}
+
+/// Checks a host before it's saved. It's passed to `ssh` as an argument, so one that looks like an
+/// option (e.g. `-oProxyCommand=...`) would be read as one.
+pub fn check_host(what: &str, host: &str) -> Result<(), String> {
+    if host.starts_with('-') || host.chars().any(char::is_whitespace) {
+        return Err(format!(
+            "the {what} can't start with '-' or contain whitespace: {host:?}"
+        ));
+    }
+    Ok(())
+}
+
+fn free_port() -> Result<u16, String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    Ok(port)
+}
+
+fn spawn_tunnel(remote: &RemoteServer, ssh_host: &str) -> Result<Tunnel, String> {
+    // NOTE: Checked again here, for remotes saved before they were checked when saving
+    check_host("host", &remote.host)?;
+    check_host("SSH host", ssh_host)?;
+    let port = free_port()?;
+    let mut command = Command::new("ssh");
+    command
+        .arg("-N")
+        .args(["-o", "BatchMode=yes"])
+        .args(["-o", "ExitOnForwardFailure=yes"])
+        .arg("-L")
+        .arg(format!("127.0.0.1:{port}:{}:{}", remote.host, remote.port));
+    if let Some(key) = &remote.ssh_key {
+        command.arg("-i").arg(key);
+    }
+    if let Some(user) = &remote.ssh_user {
+        command.arg("-l").arg(user);
+    }
+    // NOTE: `ssh_host` is `host` or `host:port`
+    match ssh_host.rsplit_once(':') {
+        Some((host, ssh_port)) if ssh_port.parse::<u16>().is_ok() => {
+            command.arg("-p").arg(ssh_port).arg("--").arg(host);
+        }
+        _ => {
+            command.arg("--").arg(ssh_host);
+        }
+    }
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("couldn't run ssh: {e}"))?;
+    let mut tunnel = Tunnel {
+        forward: Forward::Ssh(child),
+        port,
+    };
+
+    // NOTE: ssh doesn't say when the forward is ready, so wait until the port accepts connections
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let started = Instant::now();
+    loop {
+        if let Forward::Ssh(child) = &mut tunnel.forward {
+            if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+                return Err(format!("ssh tunnel to {ssh_host} exited with {status}"));
+            }
+        }
+        if TcpStream::connect_timeout(&address, POLL_INTERVAL).is_ok() {
+            debug!("ssh tunnel through {ssh_host} listening on port {port}");
+            return Ok(tunnel);
+        }
+        if started.elapsed() > TUNNEL_TIMEOUT {
+            return Err(format!("timed out opening ssh tunnel to {ssh_host}"));
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+fn pipe(mut from: TcpStream, mut to: TcpStream) {
+    let _ = io::copy(&mut from, &mut to);
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+fn forward_connection(local: TcpStream, proxy: &Proxy, host: &str, port: u16) -> io::Result<()> {
+    let remote = proxy.connect(host, port)?;
+    let (local_reader, remote_reader) = (local.try_clone()?, remote.try_clone()?);
+    spawn(move || pipe(local_reader, remote));
+    spawn(move || pipe(remote_reader, local));
+    Ok(())
+}
+
+/// Listens on a local port, and forwards each connection to the remote's database through the
+/// proxy.
+fn spawn_proxy_forward(remote: &RemoteServer, proxy: Proxy) -> Result<Tunnel, String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    // NOTE: Non-blocking, so the loop notices when the tunnel is dropped
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let (host, remote_port) = (remote.host.clone(), remote.port);
+    let accepting = stopped.clone();
+    spawn(move || {
+        while !accepting.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((local, _)) => {
+                    let (proxy, host) = (proxy.clone(), host.clone());
+                    spawn(move || {
+                        let forwarded = local
+                            .set_nonblocking(false)
+                            .and_then(|_| forward_connection(local, &proxy, &host, remote_port));
+                        if let Err(e) = forwarded {
+                            warn!("couldn't connect to {host}:{remote_port} through proxy: {e}");
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => sleep(POLL_INTERVAL),
+                Err(e) => {
+                    warn!("proxy forward stopped: {e}");
+                    break;
+                }
+            }
+        }
+    });
+    debug!(
+        "forwarding port {port} to {}:{remote_port} through proxy",
+        remote.host
+    );
+    Ok(Tunnel {
+        forward: Forward::Proxy(stopped),
+        port,
+    })
+}
+
+/// Opens a port forward to the remote's database, through its SSH jump host or its proxy, if it has
+/// either. The jump host takes precedence -- a proxy for `ssh` itself belongs in the SSH config.
+pub async fn open_tunnel(remote: &RemoteServer) -> Result<Option<Tunnel>, String> {
+    if let Some(ssh_host) = remote.ssh_host.clone() {
+        let remote = remote.clone();
+        return tauri::async_runtime::spawn_blocking(move || {
+            spawn_tunnel(&remote, &ssh_host).map(Some)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    match &remote.proxy {
+        Some(proxy) => spawn_proxy_forward(remote, Proxy::parse(proxy)?).map(Some),
+        None => Ok(None),
+    }
+}
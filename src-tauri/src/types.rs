@@ -1,7 +1,7 @@
 // Copyright (C) 2025  Athan Clark
 use chrono::{DateTime, Utc};
 
-#[derive(sqlx::FromRow, Debug, Clone)]
+#[derive(sqlx::FromRow, Clone)]
 pub struct RemoteServer {
     pub id: String,
     pub host: String,
@@ -9,7 +9,109 @@ pub struct RemoteServer {
     pub db: String,
     pub user: String,
     pub password: String,
+    pub password_command: Option<String>,
     pub db_type: String,
+    pub ssl_mode: Option<String>,
+    pub ssl_ca: Option<String>,
+    pub ssl_client_cert: Option<String>,
+    pub ssl_client_key: Option<String>,
+    pub ssh_host: Option<String>,
+    pub ssh_user: Option<String>,
+    pub ssh_key: Option<String>,
+    pub proxy: Option<String>,
+    pub namespace: Option<String>,
+    pub direction: String,
+    pub publish: bool,
+    pub is_primary: bool,
+    pub encrypt: bool,
+}
+
+/// Which way changes flow between the library and a remote.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Both,
+    /// Local changes are pushed, but the remote never changes the library -- e.g. an off-site backup.
+    Push,
+    /// The remote's changes are pulled, but the library never changes the remote -- e.g. a mirror.
+    Pull,
+    /// Like `Push`, but the remote's rows are made to match the library's even where they're newer,
+    /// or the library doesn't have them. Secondaries are synced this way, never saved with it.
+    Overwrite,
+}
+
+impl Direction {
+    pub fn parse(direction: &str) -> Result<Self, String> {
+        match direction {
+            "both" => Ok(Direction::Both),
+            "push" => Ok(Direction::Push),
+            "pull" => Ok(Direction::Pull),
+            direction => Err(format!("Unrecognized sync direction: {direction:?}")),
+        }
+    }
+
+    pub fn pushes(self) -> bool {
+        self != Direction::Pull
+    }
+
+    pub fn pulls(self) -> bool {
+        matches!(self, Direction::Both | Direction::Pull)
+    }
+
+    pub fn overwrites(self) -> bool {
+        self == Direction::Overwrite
+    }
+
+    /// Whether a local row modified at `local` replaces the remote's copy, modified at `remote`.
+    /// Compared to the second when overwriting, which is as precise as every remote keeps them.
+    pub fn replaces(self, local: DateTime<Utc>, remote: DateTime<Utc>) -> bool {
+        local > remote || (self.overwrites() && local.timestamp() != remote.timestamp())
+    }
+}
+
+impl RemoteServer {
+    /// How the remote is synced. When there's a primary, every other remote is a secondary, which
+    /// is overwritten with the library once it's been synced with the primary.
+    pub fn sync_direction(&self, has_primary: bool) -> Result<Direction, String> {
+        if has_primary && !self.is_primary {
+            Ok(Direction::Overwrite)
+        } else {
+            Direction::parse(&self.direction)
+        }
+    }
+
+    /// What's put in front of a MySQL remote's table names -- empty unless it has a namespace.
+    pub fn table_prefix(&self) -> &str {
+        self.namespace.as_deref().unwrap_or_default()
+    }
+}
+
+// NOTE: Written by hand so passwords never end up in logs
+impl std::fmt::Debug for RemoteServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteServer")
+            .field("id", &self.id)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("db", &self.db)
+            .field("user", &self.user)
+            .field("password", &"<redacted>")
+            .field("password_command", &self.password_command)
+            .field("db_type", &self.db_type)
+            .field("ssl_mode", &self.ssl_mode)
+            .field("ssl_ca", &self.ssl_ca)
+            .field("ssl_client_cert", &self.ssl_client_cert)
+            .field("ssl_client_key", &self.ssl_client_key)
+            .field("ssh_host", &self.ssh_host)
+            .field("ssh_user", &self.ssh_user)
+            .field("ssh_key", &self.ssh_key)
+            .field("proxy", &self.proxy.as_ref().map(|_| "<redacted>"))
+            .field("namespace", &self.namespace)
+            .field("direction", &self.direction)
+            .field("publish", &self.publish)
+            .field("is_primary", &self.is_primary)
+            .field("encrypt", &self.encrypt)
+            .finish()
+    }
 }
 
 #[derive(sqlx::FromRow, Debug, Clone)]
@@ -23,6 +125,22 @@ pub struct IdAndModified {
     pub modified: DateTime<Utc>,
 }
 
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct IdModifiedAndHash {
+    pub id: String,
+    pub modified: DateTime<Utc>,
+    pub content_hash: Option<String>,
+}
+
+/// A remote's summary of the documents whose ids start with `bucket`. The sum is selected as text,
+/// since it outgrows a 64-bit integer.
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct BucketHash {
+    pub bucket: String,
+    pub count: i64,
+    pub sum: String,
+}
+
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct Id {
     pub id: String,
@@ -48,4 +166,38 @@ pub struct Document {
     pub syntax: String,
     pub icon: Option<String>,
     pub icon_color: Option<String>,
+    // NOTE: Only selected where it's synced, and never pushed to encrypted remotes
+    #[sqlx(default)]
+    pub content_hash: Option<String>,
+    // NOTE: Only pushed to remotes that are published to
+    #[sqlx(default)]
+    pub rendered: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct KeyCheck {
+    pub salt: String,
+    pub check_value: String,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct BookShare {
+    pub owner: String,
+    pub book: String,
+    pub permission: String,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug, Clone)]
+pub struct Grant {
+    pub grantee: String,
+    pub permission: String,
+}
+
+/// A named copy of the owner's books and documents, kept on the remote.
+#[derive(sqlx::FromRow, serde::Serialize, Debug, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    pub created: DateTime<Utc>,
+    pub books: i64,
+    pub documents: i64,
 }
@@ -0,0 +1,265 @@
+// Copyright (C) 2025  Athan Clark
+use crate::error::SyncError;
+use crate::hashes::{book_hash, document_hash};
+use crate::types::{Book, Document, Id};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+
+/// One way a library and a remote have diverged.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Divergence {
+    /// `missing`, `differing`, `orphan` or `tombstoned`.
+    pub kind: &'static str,
+    /// `books`, `documents` or `deleted`.
+    pub table: &'static str,
+    pub id: String,
+    /// `local` or `remote` -- the side the row is on, or for differing rows, the newer side (`both`
+    /// when they were modified at the same time).
+    pub side: &'static str,
+}
+
+/// What one side holds, reduced to what's compared: modification times and hashes of every other
+/// field, plus each document's book.
+#[derive(Default)]
+pub struct Inventory {
+    books: HashMap<String, (DateTime<Utc>, String)>,
+    documents: HashMap<String, (DateTime<Utc>, String, String)>,
+    deleted: HashSet<String>,
+}
+
+impl Inventory {
+    pub fn add_book(&mut self, book: &Book) {
+        self.books
+            .insert(book.id.clone(), (book.modified, book_hash(book)));
+    }
+
+    pub fn add_document(&mut self, document: &Document) {
+        self.documents.insert(
+            document.id.clone(),
+            (
+                document.modified,
+                document.book.clone(),
+                document_hash(document),
+            ),
+        );
+    }
+
+    pub fn add_deleted(&mut self, id: String) {
+        self.deleted.insert(id);
+    }
+}
+
+/// Reads the local library, leaving out the books shared with this user and their documents, like
+/// syncing does.
+pub async fn local_inventory(
+    local_conn: &Pool<Sqlite>,
+    shared_books: &HashSet<String>,
+    shared_documents: &HashSet<String>,
+) -> Result<Inventory, SyncError> {
+    let mut inventory = Inventory::default();
+    let mut books =
+        sqlx::query_as::<_, Book>("SELECT id, name, modified, icon, icon_color, trash FROM books")
+            .fetch(local_conn);
+    while let Some(book) = books.try_next().await? {
+        if !shared_books.contains(&book.id) {
+            inventory.add_book(&book);
+        }
+    }
+    let mut documents = sqlx::query_as::<_, Document>(
+        "SELECT id, book, name, modified, content, syntax, icon, icon_color FROM documents",
+    )
+    .fetch(local_conn);
+    while let Some(document) = documents.try_next().await? {
+        if !shared_documents.contains(&document.id) {
+            inventory.add_document(&document);
+        }
+    }
+    let mut deleted = sqlx::query_as::<_, Id>("SELECT id FROM deleted").fetch(local_conn);
+    while let Some(id) = deleted.try_next().await? {
+        inventory.add_deleted(id.id);
+    }
+    Ok(inventory)
+}
+
+fn newer(local: &DateTime<Utc>, remote: &DateTime<Utc>) -> &'static str {
+    if local > remote {
+        "local"
+    } else if remote > local {
+        "remote"
+    } else {
+        "both"
+    }
+}
+
+/// Compares both sides row by row.
+pub fn compare_inventories(local: &Inventory, remote: &Inventory) -> Vec<Divergence> {
+    let mut divergences = vec![];
+    let mut push = |kind, table, id: &String, side| {
+        divergences.push(Divergence {
+            kind,
+            table,
+            id: id.clone(),
+            side,
+        })
+    };
+    let tombstoned = |id: &String| local.deleted.contains(id) || remote.deleted.contains(id);
+    for (side, this, other) in [("local", local, remote), ("remote", remote, local)] {
+        for (id, (modified, hash)) in &this.books {
+            match other.books.get(id) {
+                _ if tombstoned(id) => push("tombstoned", "books", id, side),
+                None => push("missing", "books", id, side),
+                // NOTE: Each pair is only reported once, from the local side
+                Some((other_modified, other_hash)) if side == "local" && hash != other_hash => {
+                    push("differing", "books", id, newer(modified, other_modified))
+                }
+                Some(_) => {}
+            }
+        }
+        for (id, (modified, book, hash)) in &this.documents {
+            if !this.books.contains_key(book) {
+                push("orphan", "documents", id, side);
+            }
+            match other.documents.get(id) {
+                _ if tombstoned(id) => push("tombstoned", "documents", id, side),
+                None => push("missing", "documents", id, side),
+                Some((other_modified, _, other_hash)) if side == "local" && hash != other_hash => {
+                    push(
+                        "differing",
+                        "documents",
+                        id,
+                        newer(modified, other_modified),
+                    )
+                }
+                Some(_) => {}
+            }
+        }
+        for id in this.deleted.difference(&other.deleted) {
+            push("missing", "deleted", id, side);
+        }
+    }
+    divergences.sort_by(|a, b| (a.table, &a.id, a.side).cmp(&(b.table, &b.id, b.side)));
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(id: &str, seconds: i64) -> Book {
+        Book {
+            id: id.to_string(),
+            modified: DateTime::from_timestamp(seconds, 0).unwrap(),
+            name: Some(id.to_string()),
+            icon: None,
+            icon_color: None,
+            trash: 0,
+        }
+    }
+
+    fn document(id: &str, book: &str, seconds: i64, content: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            book: book.to_string(),
+            modified: DateTime::from_timestamp(seconds, 0).unwrap(),
+            name: Some(id.to_string()),
+            content: Some(content.to_string()),
+            syntax: "md".to_string(),
+            icon: None,
+            icon_color: None,
+            content_hash: None,
+            rendered: None,
+        }
+    }
+
+    /// Both sides hold the same book with two documents.
+    fn inventories() -> (Inventory, Inventory) {
+        let mut local = Inventory::default();
+        let mut remote = Inventory::default();
+        for inventory in [&mut local, &mut remote] {
+            inventory.add_book(&book("b1", 10));
+            inventory.add_document(&document("d1", "b1", 10, "one"));
+            inventory.add_document(&document("d2", "b1", 10, "two"));
+        }
+        (local, remote)
+    }
+
+    fn found(
+        divergences: Vec<Divergence>,
+    ) -> Vec<(&'static str, &'static str, String, &'static str)> {
+        divergences
+            .into_iter()
+            .map(|d| (d.kind, d.table, d.id, d.side))
+            .collect()
+    }
+
+    #[test]
+    fn in_sync() {
+        let (local, remote) = inventories();
+        assert!(compare_inventories(&local, &remote).is_empty());
+    }
+
+    #[test]
+    fn missing() {
+        let (mut local, mut remote) = inventories();
+        local.add_book(&book("b2", 10));
+        remote.add_document(&document("d3", "b1", 10, "three"));
+        remote.add_deleted("d4".to_string());
+        assert_eq!(
+            found(compare_inventories(&local, &remote)),
+            [
+                ("missing", "books", "b2".to_string(), "local"),
+                ("missing", "deleted", "d4".to_string(), "remote"),
+                ("missing", "documents", "d3".to_string(), "remote"),
+            ]
+        );
+    }
+
+    #[test]
+    fn differing() {
+        let (mut local, mut remote) = inventories();
+        local.add_document(&document("d1", "b1", 20, "edited"));
+        remote.add_document(&document("d2", "b1", 20, "edited"));
+        remote.add_book(&book("b1", 10));
+        local.add_book(&Book {
+            name: Some("renamed".to_string()),
+            ..book("b1", 10)
+        });
+        assert_eq!(
+            found(compare_inventories(&local, &remote)),
+            [
+                ("differing", "books", "b1".to_string(), "both"),
+                ("differing", "documents", "d1".to_string(), "local"),
+                ("differing", "documents", "d2".to_string(), "remote"),
+            ]
+        );
+    }
+
+    #[test]
+    fn orphan() {
+        let (local, mut remote) = inventories();
+        remote.add_document(&document("d3", "gone", 10, "three"));
+        assert_eq!(
+            found(compare_inventories(&local, &remote)),
+            [
+                ("orphan", "documents", "d3".to_string(), "remote"),
+                ("missing", "documents", "d3".to_string(), "remote"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tombstoned() {
+        let (mut local, mut remote) = inventories();
+        local.add_deleted("d1".to_string());
+        remote.add_deleted("d1".to_string());
+        assert_eq!(
+            found(compare_inventories(&local, &remote)),
+            [
+                ("tombstoned", "documents", "d1".to_string(), "local"),
+                ("tombstoned", "documents", "d1".to_string(), "remote"),
+            ]
+        );
+    }
+}
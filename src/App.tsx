@@ -1,6 +1,7 @@
 // Copyright (C) 2025  Athan Clark
 import React from "react";
 import { __LOCAL_DB } from "./consts";
+import { describeError } from "./errors";
 import Nav from "./Nav";
 import Document from "./Document";
 import Settings from "./Settings";
@@ -72,7 +73,7 @@ function App() {
         notifications.update({
           id: "sync",
           title: "Synchronizing with database",
-          message: Array(e).join("\n") + ".\n\n Auto synchronization turned off.",
+          message: (Array.isArray(e) ? e : [e]).map(describeError).join("\n") + ".\n\n Auto synchronization turned off.",
           color: "red",
           autoClose: false,
         })
@@ -139,6 +140,10 @@ function App() {
         if (mapping.get("default_syntax")) {
           setDefaultSyntax(mapping.get("default_syntax"));
         }
+
+        if (mapping.get("mirror_directory")) {
+          await invoke("start_library_mirror");
+        }
       } catch(e) {
         console.error("Couldn't select initial values", e);
       }
@@ -2,10 +2,11 @@
 import { useState, useMemo, useRef, useEffect, useDeferredValue, startTransition } from "react";
 import { convertFileSrc, invoke } from "@tauri-apps/api/core";
 import { exists } from "@tauri-apps/plugin-fs";
-import { NativeSelect, Typography, Tabs, Grid } from "@mantine/core";
+import { Alert, NativeSelect, Typography, Tabs, Grid } from "@mantine/core";
 import { useColorScheme } from "@mantine/hooks";
 import { IconEdit, IconEye } from "@tabler/icons-react";
 import DOMPurify from "dompurify";
+import { describeError } from "../errors";
 import CodeMirror from "@uiw/react-codemirror";
 import { markdown, markdownLanguage } from "@codemirror/lang-markdown";
 import { languages } from "@codemirror/language-data";
@@ -157,6 +158,7 @@ type ViewProps = {
 function View({ value, syntax }: ViewProps) {
   const [html, setHtml] = useState("");
   const [rewrittenHtml, setRewrittenHtml] = useState("");
+  const [renderError, setRenderError] = useState<string | null>(null);
 
   useEffect(() => {
     async function go() {
@@ -167,8 +169,10 @@ function View({ value, syntax }: ViewProps) {
         } else {
           setHtml(value);
         }
+        setRenderError(null);
       } catch(e) {
         console.warn("failed to render", e);
+        setRenderError(describeError(e));
       }
     }
     go();
@@ -224,6 +228,7 @@ function View({ value, syntax }: ViewProps) {
     : (<span>Editor Type Not Supported</span>);
   return (
     <Typography>
+      {renderError && (<Alert color="red" title="Couldn't Render Document">{renderError}</Alert>)}
       <MathJaxBlock>
         {renderedValue}
       </MathJaxBlock>
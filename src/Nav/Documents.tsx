@@ -6,9 +6,11 @@ import { useState, useEffect } from "react";
 import Database from "@tauri-apps/plugin-sql";
 import { Button, Divider, Title, Accordion, NavLink, Loader, Anchor, TextInput, ColorInput, Typography, Modal, Stack } from "@mantine/core";
 import { useDisclosure } from "@mantine/hooks";
-import { IconArrowLeft, IconPlus, IconTrash, IconRecycle, IconAlertTriangle } from '@tabler/icons-react';
+import { IconArrowLeft, IconPlus, IconTrash, IconRecycle, IconAlertTriangle, IconShare, IconWorldUpload } from '@tabler/icons-react';
 import data from "@emoji-mart/data";
 import Picker from "@emoji-mart/react";
+import ShareBook from "./Share";
+import PublishBook from "./Publish";
 import "./Documents.css";
 
 export type Document = {
@@ -36,6 +38,8 @@ function Documents({ book, onSelectDocument, goBack, reload, defaultSyntax, sele
   const [bookTrash, setBookTrash] = useState<boolean | null>(null);
   const [openedDeleteBook, { open: openDeleteBook, close: closeDeleteBook }] = useDisclosure();
   const [openedTrashBook, { open: openTrashBook, close: closeTrashBook }] = useDisclosure();
+  const [openedShareBook, { open: openShareBook, close: closeShareBook }] = useDisclosure();
+  const [openedPublishBook, { open: openPublishBook, close: closePublishBook }] = useDisclosure();
   const [openedEmojiPicker, { open: openEmojiPicker, close: closeEmojiPicker }] = useDisclosure();
 
   function actuallyReload() {
@@ -189,6 +193,12 @@ function Documents({ book, onSelectDocument, goBack, reload, defaultSyntax, sele
         </Typography>
         <Button color="red" onClick={deleteBook}>Delete {bookName}</Button>
       </Modal>
+      <Modal opened={openedShareBook} onClose={closeShareBook} title={`Share ${bookName}`}>
+        {book && (<ShareBook book={book} />)}
+      </Modal>
+      <Modal opened={openedPublishBook} onClose={closePublishBook} title={`Publish ${bookName}`}>
+        {book && (<PublishBook book={book} />)}
+      </Modal>
       <Modal opened={openedEmojiPicker} onClose={closeEmojiPicker} title="Pick Icon">
         <Picker data={data} onEmojiSelect={(emoji: { native: string }) => {
           changeBookIcon(emoji.native);
@@ -242,6 +252,16 @@ function Documents({ book, onSelectDocument, goBack, reload, defaultSyntax, sele
                     value={bookIconColor || ""}
                     onChange={(c) => changeBookIconColor(c)}
                     />
+                  {
+                    !(book === "trash" || book === "default") && (
+                      <Button fullWidth variant="default" onClick={openShareBook} leftSection={<IconShare size={14} />}>Share Book</Button>
+                    )
+                  }
+                  {
+                    !(book === "trash") && (
+                      <Button fullWidth variant="default" onClick={openPublishBook} leftSection={<IconWorldUpload size={14} />}>Publish Book</Button>
+                    )
+                  }
                   {
                     !(book === "trash") && (
                       bookTrash
@@ -0,0 +1,77 @@
+// Copyright (C) 2025  Athan Clark
+import { __LOCAL_DB } from "../consts";
+import { useState, useEffect } from "react";
+import Database from "@tauri-apps/plugin-sql";
+import { Stack, Switch, Typography } from "@mantine/core";
+
+type Remote = {
+  id: string;
+  host: string;
+  db: string;
+  user: string;
+  published: number | boolean;
+};
+
+type PublishBookProps = {
+  book: string;
+};
+
+// NOTE: Books are published to the remotes set to "Publish Picked Books Only" on their next sync
+function PublishBook({ book }: PublishBookProps) {
+  const [remotes, setRemotes] = useState<Remote[]>([]);
+
+  function reload() {
+    async function go() {
+      try {
+        const db = await Database.load(__LOCAL_DB);
+        const rs = await db.select<Remote[]>(
+          "SELECT r.id, r.host, r.db, r.user, EXISTS (SELECT 1 FROM published_books p WHERE p.remote = r.id AND p.book = $1) AS published FROM remote_servers r WHERE r.publish = 1",
+          [book]
+        );
+        setRemotes(rs.map(r => ({ ...r, published: r.published === 1 })));
+      } catch(e) {
+        console.error("Fetching remote servers Failed", e);
+      }
+    }
+    go();
+  }
+
+  useEffect(reload, [book]);
+
+  function setPublished(r: Remote, published: boolean) {
+    async function go() {
+      try {
+        const db = await Database.load(__LOCAL_DB);
+        if (published) {
+          await db.execute("INSERT OR IGNORE INTO published_books (remote, book) VALUES ($1, $2)", [r.id, book]);
+        } else {
+          await db.execute("DELETE FROM published_books WHERE remote = $1 AND book = $2", [r.id, book]);
+        }
+        reload();
+      } catch(e) {
+        console.error("Publishing book Failed", e);
+      }
+    }
+    go();
+  }
+
+  if (remotes.length === 0) {
+    return (<Typography>Add a MySQL or PostgreSQL remote server set to publish picked books only to publish books to it.</Typography>);
+  }
+
+  return (
+    <Stack>
+      {remotes.map(r => (
+        <Switch
+          key={r.id}
+          label={`${r.user}@${r.host}/${r.db}`}
+          checked={r.published === true}
+          onChange={e => setPublished(r, e.currentTarget.checked)}
+        />
+      ))}
+      <Typography>Books in the trash aren't published, and stop being published until they're restored.</Typography>
+    </Stack>
+  );
+}
+
+export default PublishBook;
@@ -0,0 +1,136 @@
+// Copyright (C) 2025  Athan Clark
+import { __LOCAL_DB } from "../consts";
+import { describeError } from "../errors";
+import { useState, useEffect } from "react";
+import Database from "@tauri-apps/plugin-sql";
+import { invoke } from "@tauri-apps/api/core";
+import { Alert, ActionIcon, Button, NativeSelect, Stack, Table, TextInput, Typography } from "@mantine/core";
+import { IconTrash } from "@tabler/icons-react";
+
+type Remote = {
+  id: string;
+  host: string;
+  db: string;
+  user: string;
+};
+
+type Grant = {
+  grantee: string;
+  permission: "read" | "write";
+};
+
+type ShareBookProps = {
+  book: string;
+};
+
+// NOTE: Books are shared with other users of the same MySQL or PostgreSQL remote
+function ShareBook({ book }: ShareBookProps) {
+  const [remotes, setRemotes] = useState<Remote[]>([]);
+  const [remote, setRemote] = useState<string | null>(null);
+  const [grants, setGrants] = useState<Grant[] | null>(null);
+  const [grantee, setGrantee] = useState<string>("");
+  const [permission, setPermission] = useState<"read" | "write">("read");
+  const [shareError, setShareError] = useState<string | null>(null);
+
+  useEffect(() => {
+    async function go() {
+      try {
+        const db = await Database.load(__LOCAL_DB);
+        const rs = await db.select<Remote[]>(
+          "SELECT id, host, db, user FROM remote_servers WHERE db_type IN ('mysql', 'postgresql')",
+          []
+        );
+        setRemotes(rs);
+        setRemote(rs.length > 0 ? rs[0].id : null);
+      } catch(e) {
+        console.error("Fetching remote servers Failed", e);
+      }
+    }
+    go();
+  }, []);
+
+  function reloadGrants() {
+    async function go() {
+      setGrants(null);
+      try {
+        setGrants(await invoke("book_grants", { dbId: remote, book }));
+        setShareError(null);
+      } catch(e) {
+        setGrants([]);
+        setShareError(describeError(e));
+      }
+    }
+    remote && go();
+  }
+
+  useEffect(reloadGrants, [remote, book]);
+
+  function share() {
+    async function go() {
+      try {
+        await invoke("share_book", { dbId: remote, book, grantee: grantee.trim(), permission });
+        setGrantee("");
+        reloadGrants();
+      } catch(e) {
+        setShareError(describeError(e));
+      }
+    }
+    go();
+  }
+
+  function unshare(g: Grant) {
+    async function go() {
+      try {
+        await invoke("unshare_book", { dbId: remote, book, grantee: g.grantee });
+        reloadGrants();
+      } catch(e) {
+        setShareError(describeError(e));
+      }
+    }
+    go();
+  }
+
+  if (remotes.length === 0) {
+    return (<Typography>Add a MySQL or PostgreSQL remote server to share books with its other users.</Typography>);
+  }
+
+  return (
+    <Stack>
+      {shareError && (<Alert color="red" title="Couldn't Share Book">{shareError}</Alert>)}
+      <NativeSelect
+        label="Remote Server"
+        value={remote || ""}
+        onChange={e => setRemote(e.currentTarget.value)}
+        data={remotes.map(r => ({ value: r.id, label: `${r.user}@${r.host}/${r.db}` }))}
+      />
+      <Table>
+        <Table.Tbody>
+          {(grants || []).map(g => (
+            <Table.Tr key={g.grantee}>
+              <Table.Td>{g.grantee}</Table.Td>
+              <Table.Td>{g.permission === "write" ? "Can Edit" : "Can Read"}</Table.Td>
+              <Table.Td>
+                <ActionIcon color="red" onClick={() => unshare(g)}><IconTrash /></ActionIcon>
+              </Table.Td>
+            </Table.Tr>
+          ))}
+        </Table.Tbody>
+      </Table>
+      <TextInput
+        label="User"
+        description="Their username on the remote server"
+        value={grantee}
+        onChange={e => setGrantee(e.currentTarget.value)}
+      />
+      <NativeSelect
+        label="Permission"
+        value={permission}
+        onChange={e => setPermission(e.currentTarget.value === "write" ? "write" : "read")}
+        data={[{ value: "read", label: "Can Read" }, { value: "write", label: "Can Edit" }]}
+      />
+      <Button disabled={grantee.trim() === "" || grants === null} onClick={share}>Share</Button>
+    </Stack>
+  );
+}
+
+export default ShareBook;
@@ -0,0 +1,114 @@
+// Copyright (C) 2025  Athan Clark
+import { describeError } from "./errors";
+import { useState, useEffect } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { Alert, Button, Group, Stack, Table, TextInput, Typography } from "@mantine/core";
+
+// NOTE: Mirrors `Snapshot` in src-tauri/src/types.rs
+type Snapshot = {
+  name: string;
+  created: string;
+  books: number;
+  documents: number;
+};
+
+type RemoteSnapshotsProps = {
+  dbId: string;
+  onRestored: () => void;
+};
+
+// NOTE: Snapshots are taken of the remote's rows, so they have the library as of its last sync
+function RemoteSnapshots({ dbId, onRestored }: RemoteSnapshotsProps) {
+  const [snapshots, setSnapshots] = useState<Snapshot[] | null>(null);
+  const [name, setName] = useState<string>("");
+  const [confirming, setConfirming] = useState<string | null>(null);
+  const [working, setWorking] = useState<boolean>(false);
+  const [snapshotError, setSnapshotError] = useState<string | null>(null);
+
+  function reload() {
+    async function go() {
+      setSnapshots(null);
+      try {
+        setSnapshots(await invoke("remote_snapshots", { dbId }));
+        setSnapshotError(null);
+      } catch(e) {
+        setSnapshots([]);
+        setSnapshotError(describeError(e));
+      }
+    }
+    go();
+  }
+
+  useEffect(reload, [dbId]);
+
+  function create() {
+    async function go() {
+      setWorking(true);
+      try {
+        await invoke("create_remote_snapshot", { dbId, name: name.trim() });
+        setName("");
+        reload();
+      } catch(e) {
+        setSnapshotError(describeError(e));
+      }
+      setWorking(false);
+    }
+    go();
+  }
+
+  function restore(snapshot: string) {
+    async function go() {
+      setWorking(true);
+      try {
+        await invoke("restore_remote_snapshot", { dbId, name: snapshot });
+        setConfirming(null);
+        setSnapshotError(null);
+        onRestored();
+      } catch(e) {
+        setSnapshotError(describeError(e));
+      }
+      setWorking(false);
+    }
+    go();
+  }
+
+  return (
+    <Stack>
+      {snapshotError && (<Alert color="red" title="Snapshot Failed">{snapshotError}</Alert>)}
+      <Table>
+        <Table.Tbody>
+          {(snapshots || []).map(s => (
+            <Table.Tr key={s.name}>
+              <Table.Td>{s.name}</Table.Td>
+              <Table.Td>{new Date(s.created).toLocaleString()}</Table.Td>
+              <Table.Td>{s.books} books, {s.documents} documents</Table.Td>
+              <Table.Td>
+                {confirming === s.name ? (
+                  <Group gap="xs">
+                    <Button color="red" size="xs" loading={working} onClick={() => restore(s.name)}>Replace Library</Button>
+                    <Button variant="default" size="xs" disabled={working} onClick={() => setConfirming(null)}>Cancel</Button>
+                  </Group>
+                ) : (
+                  <Button variant="default" size="xs" disabled={working} onClick={() => setConfirming(s.name)}>Restore</Button>
+                )}
+              </Table.Td>
+            </Table.Tr>
+          ))}
+        </Table.Tbody>
+      </Table>
+      {snapshots !== null && snapshots.length === 0 && (<Typography>This remote doesn't have any snapshots yet.</Typography>)}
+      <Typography>
+        Restoring replaces the library with the snapshot -- anything added since is deleted -- and syncs it to every remote.
+      </Typography>
+      <TextInput
+        label="Name"
+        description="e.g. before upgrading"
+        value={name}
+        onChange={e => setName(e.currentTarget.value)}
+      />
+      <Button disabled={name.trim() === "" || snapshots === null} loading={working} onClick={create}>Create Snapshot</Button>
+    </Stack>
+  );
+}
+
+export default RemoteSnapshots;
@@ -0,0 +1,31 @@
+// Copyright (C) 2025  Athan Clark
+
+// NOTE: Mirrors `ScriptoriumError` in src-tauri/src/error.rs, which every command fails with
+export type ScriptoriumError =
+  | { code: "auth_failed", message: string }
+  | { code: "tls_failed", message: string }
+  | { code: "unreachable", message: string }
+  | { code: "schema_mismatch", message: string }
+  | { code: "encryption", message: string }
+  | { code: "parse", message: string, line: number | null }
+  | { code: "database", message: string }
+  | { code: "other", message: string };
+
+export function isScriptoriumError(e: unknown): e is ScriptoriumError {
+  return typeof e === "object" && e !== null && "code" in e && "message" in e;
+}
+
+const hints: { [C in ScriptoriumError["code"]]?: string } = {
+  auth_failed: "check the remote's user and password",
+  tls_failed: "check the remote's SSL mode and certificates",
+  unreachable: "check the remote's host and port, SSH tunnel and proxy",
+  encryption: "unlock the library with the passphrase the remote was encrypted with",
+};
+
+export function describeError(e: unknown): string {
+  if (!isScriptoriumError(e)) {
+    return String(e);
+  }
+  const hint = hints[e.code];
+  return hint ? `${e.message} -- ${hint}` : e.message;
+}